@@ -0,0 +1,234 @@
+//! A non-blocking interface to the AUR.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use {Aur, Error, Package};
+
+/// An AUR client whose queries return futures, for use from event loops.
+///
+/// Hyper 0.6 only does blocking I/O, so each query runs on its own thread using a shared `Aur`
+/// and wakes the task polling it when done. Queries start as soon as they're made, not when the
+/// future is first polled.
+///
+/// Dropping a future (or calling `AurFuture::cancel`) cancels its query: the request stops at
+/// the next chunk of the response it's reading, or before its next retry, and its connection is
+/// closed rather than reused. A request still waiting on the server stops when data arrives or
+/// the client's timeout expires, so set one to bound how long its thread lingers.
+#[derive(Clone)]
+pub struct AsyncAur {
+    aur: Arc<Aur>,
+}
+
+impl AsyncAur {
+    /// Wrap a configured client.
+    pub fn new(aur: Aur) -> AsyncAur {
+        AsyncAur::from_arc(Arc::new(aur))
+    }
+
+    /// Wrap a client that's also used elsewhere.
+    pub fn from_arc(aur: Arc<Aur>) -> AsyncAur {
+        AsyncAur { aur: aur }
+    }
+
+    /// The underlying blocking client.
+    pub fn blocking(&self) -> &Aur {
+        &self.aur
+    }
+
+    /// See `Aur::search`.
+    pub fn search(&self, pat: &str) -> AurFuture<Vec<Package>> {
+        let pat = pat.to_owned();
+        self.spawn(move |aur| aur.search(&pat))
+    }
+
+    /// See `Aur::info`.
+    pub fn info(&self, name: &str) -> AurFuture<Option<Package>> {
+        let name = name.to_owned();
+        self.spawn(move |aur| aur.info(&name))
+    }
+
+    /// See `Aur::multiinfo`.
+    pub fn multiinfo<'a, I>(&self, names: I) -> AurFuture<Vec<Package>>
+        where I: IntoIterator<Item = &'a str>,
+    {
+        let names: Vec<String> = names.into_iter().map(str::to_owned).collect();
+        self.spawn(move |aur| aur.multiinfo(names.iter().map(|n| &n[..])))
+    }
+
+    /// Run any query against the underlying client in the background.
+    pub fn spawn<T, F>(&self, f: F) -> AurFuture<T>
+        where T: Send + 'static,
+              F: FnOnce(&Aur) -> Result<T, Error> + Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(Shared {
+            result: None,
+            waker: None,
+        }));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let aur = self.aur.clone();
+        let theirs = shared.clone();
+        let flag = cancelled.clone();
+        thread::spawn(move || {
+            set_cancel_flag(Some(flag));
+            let result = if is_cancelled() {
+                Err(Error::Cancelled)
+            } else {
+                f(&aur)
+            };
+            let mut shared = theirs.lock().unwrap();
+            shared.result = Some(result);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        });
+        AurFuture {
+            shared: shared,
+            cancelled: cancelled,
+        }
+    }
+}
+
+thread_local! {
+    /// The cancellation flag of the query running on this thread, if it's an `AsyncAur` worker.
+    static CANCELLED: RefCell<Option<Arc<AtomicBool>>> = RefCell::new(None)
+}
+
+/// Whether the query running on this thread has been cancelled.
+pub fn is_cancelled() -> bool {
+    CANCELLED.with(|c| c.borrow().as_ref().map(|f| f.load(Ordering::SeqCst)).unwrap_or(false))
+}
+
+/// The cancellation flag of the query running on this thread, for sharing with threads that
+/// work on the same query.
+pub fn cancel_flag() -> Option<Arc<AtomicBool>> {
+    CANCELLED.with(|c| c.borrow().clone())
+}
+
+/// Make this thread's requests stop when `flag` (from `cancel_flag`) is set.
+pub fn set_cancel_flag(flag: Option<Arc<AtomicBool>>) {
+    CANCELLED.with(|c| *c.borrow_mut() = flag);
+}
+
+struct Shared<T> {
+    result: Option<Result<T, Error>>,
+    waker: Option<Waker>,
+}
+
+/// The eventual result of an `AsyncAur` query.
+pub struct AurFuture<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<T> AurFuture<T> {
+    /// Cancel the query. If it hasn't finished yet, the future resolves to `Error::Cancelled`
+    /// once its request has stopped.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<T> Drop for AurFuture<T> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl<T> Future for AurFuture<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+    use std::time::Duration;
+
+    use super::*;
+    use Error;
+    use test_util::*;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Poll `future` on this thread until it's ready.
+    fn block_on<F: Future>(mut future: F) -> F::Output {
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            // The future isn't moved again before it's dropped.
+            let pinned = unsafe { Pin::new_unchecked(&mut future) };
+            match pinned.poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn resolves_queries() {
+        let server = TestServer::new(vec![
+            ok(&envelope("search", &[package(1, "foo", &[])])),
+            ok(&envelope("multiinfo", &[package(2, "bar", &[])])),
+        ]);
+        let aur = AsyncAur::new(server.aur());
+        assert_eq!(block_on(aur.search("foo")).unwrap()[0].name, "foo");
+        assert_eq!(block_on(aur.info("bar")).unwrap().unwrap().name, "bar");
+    }
+
+    #[test]
+    fn cancelled_queries_resolve_to_cancelled() {
+        let body = envelope("search", &[package(1, "foo", &[])]);
+        let server = TestServer::new(vec![Reply::Delay(Duration::from_millis(300), Box::new(ok(&body)))]);
+        let aur = AsyncAur::new(server.aur());
+        let future = aur.search("foo");
+        future.cancel();
+        match block_on(future) {
+            Err(Error::Cancelled) => (),
+            r => panic!("expected a cancellation, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn dropping_a_future_cancels_parallel_lookups() {
+        let names: Vec<String> = (0..500).map(|i| format!("synthetic-package-{:03}", i)).collect();
+        let delay = Duration::from_millis(200);
+        let server = TestServer::new((0..20).map(|i| {
+            Reply::Delay(delay, Box::new(ok(&envelope("multiinfo", &[package(i, "foo", &[])]))))
+        }));
+        let aur = AsyncAur::new(server.aur());
+        let chunks = aur.blocking().chunk_args("multiinfo", names.iter().map(|n| &n[..])).len();
+        assert!(chunks >= 4);
+
+        let future = aur.spawn(move |aur| aur.multiinfo_parallel(names.iter().map(|n| &n[..]), 1));
+        thread::sleep(delay / 2);
+        drop(future);
+        thread::sleep(delay * chunks as u32);
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -0,0 +1,127 @@
+//! Caching package lookups in memory.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use {dedup_args, Aur, Error, Package};
+
+/// Wraps an `Aur`, remembering `info` and `multiinfo` results for `ttl` after they're fetched.
+///
+/// Packages that don't exist are remembered too. The cache is shared between threads and is
+/// never pruned, only overwritten, so call `clear` now and then in long-running programs.
+pub struct CachingAur {
+    aur: Aur,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, Option<Package>)>>,
+}
+
+impl CachingAur {
+    pub fn new(aur: Aur, ttl: Duration) -> CachingAur {
+        CachingAur {
+            aur: aur,
+            ttl: ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The underlying client, for uncached queries.
+    pub fn aur(&self) -> &Aur {
+        &self.aur
+    }
+
+    /// Forget every cached result.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Forget the cached result for `name`, if any.
+    pub fn invalidate(&self, name: &str) {
+        self.cache.lock().unwrap().remove(name);
+    }
+
+    /// Like `Aur::info`, but answered from the cache when possible.
+    pub fn info(&self, name: &str) -> Result<Option<Package>, Error> {
+        let mut pkgs = try!(self.lookup(vec![name]));
+        Ok(pkgs.pop().and_then(|p| p))
+    }
+
+    /// Like `Aur::multiinfo`, but only names that aren't cached are requested. Packages are
+    /// returned in the order they were named.
+    pub fn multiinfo<'a, I>(&self, names: I) -> Result<Vec<Package>, Error>
+        where I: IntoIterator<Item = &'a str>,
+    {
+        let names = try!(dedup_args(names));
+        Ok(try!(self.lookup(names)).into_iter().filter_map(|p| p).collect())
+    }
+
+    fn lookup(&self, names: Vec<&str>) -> Result<Vec<Option<Package>>, Error> {
+        let missing: Vec<&str> = {
+            let cache = self.cache.lock().unwrap();
+            names.iter()
+                .cloned()
+                .filter(|name| match cache.get(*name) {
+                    Some(&(fetched, _)) => fetched.elapsed() >= self.ttl,
+                    None => true,
+                })
+                .collect()
+        };
+        // Don't hold the lock across the request.
+        let fetched = if missing.is_empty() {
+            Vec::new()
+        } else {
+            try!(self.aur.multiinfo(missing.iter().cloned()))
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        for name in missing {
+            let pkg = fetched.iter().find(|p| p.name == name).cloned();
+            cache.insert(name.to_owned(), (now, pkg));
+        }
+        Ok(names.iter()
+            .map(|name| cache.get(*name).and_then(|&(_, ref pkg)| pkg.clone()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use test_util::*;
+
+    #[test]
+    fn answers_repeat_lookups_from_the_cache() {
+        let server = TestServer::new(vec![
+            ok(&envelope("multiinfo", &[package(1, "foo", &[])])),
+            ok(&envelope("multiinfo", &[])),
+            ok(&envelope("multiinfo", &[package(2, "bar", &[])])),
+        ]);
+        let aur = CachingAur::new(server.aur(), Duration::from_secs(60));
+        assert_eq!(aur.info("foo").unwrap().unwrap().name, "foo");
+        assert!(aur.info("missing").unwrap().is_none());
+        assert_eq!(aur.info("foo").unwrap().unwrap().name, "foo");
+        assert!(aur.info("missing").unwrap().is_none());
+        assert_eq!(server.requests().len(), 2);
+
+        let names: Vec<String> = aur.multiinfo(vec!["foo", "bar"]).unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["foo", "bar"]);
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(query_values(&server.targets()[2], "arg[]"), vec!["bar"]);
+    }
+
+    #[test]
+    fn invalidated_names_are_fetched_again() {
+        let server = TestServer::new(vec![
+            ok(&envelope("multiinfo", &[package(1, "foo", &[])])),
+            ok(&envelope("multiinfo", &[package(1, "foo", &[("Version", r#""2.0-1""#)])])),
+        ]);
+        let aur = CachingAur::new(server.aur(), Duration::from_secs(60));
+        aur.info("foo").unwrap();
+        aur.invalidate("foo");
+        assert_eq!(aur.info("foo").unwrap().unwrap().version, "2.0-1");
+        assert_eq!(server.requests().len(), 2);
+    }
+}
@@ -0,0 +1,187 @@
+//! Scraping package comments from the AUR website.
+//!
+//! The RPC doesn't expose comments, so they're pulled out of the package page's HTML. This looks
+//! for a handful of ids and classes rather than the full layout, so that small markup changes
+//! don't break it.
+
+use chrono::naive::datetime::NaiveDateTime;
+
+/// A comment on a package's AUR page.
+#[derive(Clone, Debug)]
+pub struct Comment {
+    pub id: u64,
+    pub author: String,
+    /// When the comment was posted (UTC), if the date could be read.
+    pub date: Option<NaiveDateTime>,
+    /// The comment's text, with markup removed.
+    pub body: String,
+}
+
+/// Extract the comments from a package page.
+pub fn parse_comments(html: &str) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("id=\"comment-") {
+        rest = &rest[start + "id=\"comment-".len()..];
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        // Skips the "comment-N-content" ids, among others.
+        if digits == 0 || !rest[digits..].starts_with('"') {
+            continue;
+        }
+        let id = match rest[..digits].parse() {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        let header = match rest.find("</h4>") {
+            Some(end) => &rest[..end],
+            None => break,
+        };
+        let author = header.find("<a")
+            .map(|i| text(inner(&header[i..])))
+            .unwrap_or_default();
+        let date = header.find("class=\"date\"")
+            .map(|i| text(inner(&header[i..])))
+            .and_then(|d| parse_date(&d));
+
+        let body = match rest.find(&format!("id=\"comment-{}-content\"", id)[..]) {
+            Some(i) => text(div_contents(&rest[i..])),
+            None => String::new(),
+        };
+        comments.push(Comment {
+            id: id,
+            author: author,
+            date: date,
+            body: body,
+        });
+    }
+    comments
+}
+
+/// The contents of the element whose start tag `s` is inside, up to its first child's end.
+fn inner(s: &str) -> &str {
+    let s = match s.find('>') {
+        Some(i) => &s[i + 1..],
+        None => return "",
+    };
+    match s.find('<') {
+        Some(i) => &s[..i],
+        None => s,
+    }
+}
+
+/// The contents of the `div` whose start tag `s` is inside, counting nested `div`s.
+fn div_contents(s: &str) -> &str {
+    let s = match s.find('>') {
+        Some(i) => &s[i + 1..],
+        None => return "",
+    };
+    let mut depth = 1;
+    let mut pos = 0;
+    while let Some(i) = s[pos..].find("div") {
+        let at = pos + i;
+        if s[..at].ends_with("</") {
+            depth -= 1;
+            if depth == 0 {
+                return &s[..at - 2];
+            }
+        } else if s[..at].ends_with('<') {
+            depth += 1;
+        }
+        pos = at + 3;
+    }
+    s
+}
+
+const BLOCK_TAGS: &'static [&'static str] = &["p", "br", "div", "pre", "li", "ul", "ol", "blockquote"];
+
+/// Strip tags, decode the common entities, and tidy whitespace.
+fn text(html: &str) -> String {
+    let mut out = String::new();
+    let mut tag = None;
+    for c in html.chars() {
+        match (c, tag.take()) {
+            ('<', None) => tag = Some(String::new()),
+            ('>', Some(t)) => {
+                // Inline tags disappear; block tags end a line.
+                let name: String = t.trim_left_matches('/')
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric())
+                    .collect();
+                if BLOCK_TAGS.contains(&&name.to_lowercase()[..]) {
+                    out.push('\n');
+                }
+            },
+            (c, Some(mut t)) => {
+                t.push(c);
+                tag = Some(t);
+            },
+            (c, None) => out.push(c),
+        }
+    }
+    let out = out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#039;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&");
+    let lines: Vec<String> = out.lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    let mut text = String::new();
+    for line in lines.iter().skip_while(|l| l.is_empty()) {
+        if line.is_empty() && text.ends_with("\n\n") {
+            continue;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text.trim_right().to_owned()
+}
+
+/// Parse a date like `2018-05-01 12:34 (UTC)`.
+fn parse_date(date: &str) -> Option<NaiveDateTime> {
+    let date = date.trim().trim_right_matches("(UTC)").trim();
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &'static str = r##"
+<div class="comments package-comments">
+<h4 id="comment-12" class="comment-header">
+    <a href="/account/alice">alice</a> commented on
+    <a href="#comment-12" class="date">2018-05-01 12:34 (UTC)</a>
+</h4>
+<div id="comment-12-content" class="article-content">
+    <div><p>Needs <code>foo&gt;=2</code> &amp; a rebuild.</p>
+    <p>Then   it   works.</p></div>
+</div>
+<h4 id="comment-7" class="comment-header">
+    <a href="/account/bob">bob</a> commented on
+    <a href="#comment-7" class="date">not a date</a>
+</h4>
+<div id="comment-7-content" class="article-content"><p>Thanks!</p></div>
+</div>
+"##;
+
+    #[test]
+    fn scrapes_comments() {
+        let comments = parse_comments(PAGE);
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0].id, 12);
+        assert_eq!(comments[0].author, "alice");
+        assert_eq!(comments[0].date.unwrap().to_string(), "2018-05-01 12:34:00");
+        assert_eq!(comments[0].body, "Needs foo>=2 & a rebuild.\n\nThen it works.");
+        assert_eq!(comments[1].author, "bob");
+        assert!(comments[1].date.is_none());
+        assert_eq!(comments[1].body, "Thanks!");
+    }
+
+    #[test]
+    fn pages_without_comments_are_empty() {
+        assert!(parse_comments("<html><body>No comments</body></html>").is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+//! Parsing dependency strings like `foo>=1.2`.
+
+use std::fmt;
+
+/// A version comparison in a dependency.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepOp {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl DepOp {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DepOp::Lt => "<",
+            DepOp::Le => "<=",
+            DepOp::Eq => "=",
+            DepOp::Ge => ">=",
+            DepOp::Gt => ">",
+        }
+    }
+}
+
+/// A dependency on a package, possibly bounded by a version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    /// The comparison, if the dependency has a bound. `version` is set exactly when this is.
+    pub op: Option<DepOp>,
+    /// The bound, including any epoch and pkgrel. Parse it with `Version` to compare it.
+    pub version: Option<String>,
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str(&self.name));
+        if let (Some(op), Some(version)) = (self.op, self.version.as_ref()) {
+            try!(write!(f, "{}{}", op.as_str(), version));
+        }
+        Ok(())
+    }
+}
+
+/// Split a dependency like `foo>=1:1.2-3` into its name, comparison and bound.
+pub fn parse_dep(s: &str) -> Dependency {
+    let s = s.trim();
+    let i = match s.find(|c| c == '<' || c == '>' || c == '=') {
+        Some(i) => i,
+        None => {
+            return Dependency {
+                name: s.to_owned(),
+                op: None,
+                version: None,
+            }
+        },
+    };
+    let rest = &s[i..];
+    let (op, len) = if rest.starts_with("<=") {
+        (DepOp::Le, 2)
+    } else if rest.starts_with(">=") {
+        (DepOp::Ge, 2)
+    } else if rest.starts_with('<') {
+        (DepOp::Lt, 1)
+    } else if rest.starts_with('>') {
+        (DepOp::Gt, 1)
+    } else {
+        (DepOp::Eq, 1)
+    };
+    Dependency {
+        name: s[..i].to_owned(),
+        op: Some(op),
+        version: Some(rest[len..].to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bounds() {
+        let dep = parse_dep(" foo>=1:1.2-3 ");
+        assert_eq!(dep.name, "foo");
+        assert_eq!(dep.op, Some(DepOp::Ge));
+        assert_eq!(dep.version, Some("1:1.2-3".to_owned()));
+        assert_eq!(dep.to_string(), "foo>=1:1.2-3");
+
+        let ops = [("a<1", DepOp::Lt), ("a<=1", DepOp::Le), ("a=1", DepOp::Eq), ("a>1", DepOp::Gt)];
+        for &(s, op) in &ops {
+            assert_eq!(parse_dep(s).op, Some(op));
+            assert_eq!(parse_dep(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn unbounded_dependencies_are_just_names() {
+        assert_eq!(parse_dep("python-foo"), Dependency {
+            name: "python-foo".to_owned(),
+            op: None,
+            version: None,
+        });
+    }
+}
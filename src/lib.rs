@@ -1,49 +1,543 @@
-// TODO: Use the json stream parser and write some macros!
+// TODO: Write some macros!
 
 extern crate url;
 extern crate hyper;
 extern crate rustc_serialize;
 extern crate chrono;
+extern crate flate2;
+
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 #[macro_use]
 extern crate log;
 extern crate env_logger;
 
-use rustc_serialize::json::{self, Json};
+use rustc_serialize::json::{self, Json, JsonEvent, Parser, StackElement};
 
-use url::Url;
-use hyper::client::{Client, RedirectPolicy};
+use url::{Url, UrlParser, form_urlencoded};
+use hyper::client::{self, pool, Client, Pool, Response};
+use hyper::net::{HttpsConnector, NetworkConnector, NetworkStream, Ssl};
+use hyper::header::{AcceptEncoding, ContentEncoding, ContentLength, ContentType, Encoding, Location, UserAgent,
+                    qitem};
+use hyper::mime::{Mime, SubLevel, TopLevel};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use chrono::UTC;
 use chrono::naive::datetime::NaiveDateTime;
+use std::cmp::{self, Ordering};
+use std::env;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter;
+use std::mem;
 use std::io;
+use std::fmt;
+use std::str::FromStr;
+use std::hash::{Hash, Hasher};
 use std::i64;
 use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::thread;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "serde")]
+mod timestamp;
+mod async_aur;
+mod cache;
+mod comments;
+mod dep;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
+mod proxy;
+mod srcinfo;
+#[cfg(test)]
+mod test_util;
+mod version;
+
+pub use async_aur::{AsyncAur, AurFuture};
+pub use cache::CachingAur;
+pub use comments::Comment;
+pub use dep::{parse_dep, DepOp, Dependency};
+pub use srcinfo::SrcInfo;
+pub use version::Version;
+use proxy::ProxyConnector;
+pub use hyper::header::Headers;
+pub use hyper::net::Openssl;
 pub use hyper::status::StatusCode as HttpStatus;
 pub use rustc_serialize::json::ErrorCode as ParseError;
+pub use url::ParseError as UrlError;
+
+/// The live AUR RPC endpoint.
+const AUR_BASE: &'static str = "https://aur.archlinux.org/rpc/";
+
+/// The live AUR website.
+const AUR_WEB: &'static str = "https://aur.archlinux.org";
+
+/// The RPC protocol version spoken by this library.
+const RPC_VERSION: &'static str = "5";
+
+/// The longest request URL we'll send; the AUR rejects URLs over about 4000 bytes.
+const MAX_URL_LEN: usize = 3800;
+
+/// The shortest term the AUR accepts for name and description searches, in bytes.
+const MIN_SEARCH_LEN: usize = 2;
+
+/// The most search results `Aur::search_full` will look up in full.
+const MAX_SEARCH_FULL: usize = 1000;
+
+/// The User-Agent sent when none has been set.
+const DEFAULT_USER_AGENT: &'static str = concat!("rust-aur/", env!("CARGO_PKG_VERSION"));
+
+/// How long to wait on a stalled connection when no timeout has been set.
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// The delay before the first retry; it doubles with every further attempt.
+const RETRY_BACKOFF_MS: u64 = 500;
+
+/// The longest `Retry-After` delay to sleep through; asking for longer fails with the response's
+/// `Error::RateLimited` instead.
+const MAX_RETRY_AFTER_SECS: u64 = 60;
+
+/// The default cap on the size of a (decoded) response body.
+const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 << 20;
+
+/// How many idle connections to keep open for reuse, matching hyper's default.
+const DEFAULT_MAX_IDLE_CONNECTIONS: usize = 5;
+
+/// How the client handles HTTP redirects.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Don't follow redirects; they fail as `Error::Http`.
+    FollowNone,
+    /// Follow every redirect.
+    FollowAll,
+    /// Follow at most this many redirects per request.
+    FollowN(usize),
+}
+
+impl RedirectPolicy {
+    /// Returns true if a request that has already been redirected `count` times may be
+    /// redirected again.
+    fn allows(&self, count: usize) -> bool {
+        match *self {
+            RedirectPolicy::FollowNone => false,
+            RedirectPolicy::FollowAll => true,
+            RedirectPolicy::FollowN(max) => count < max,
+        }
+    }
+}
+
+/// Configures and builds an `Aur` client.
+#[derive(Clone, Debug)]
+pub struct AurBuilder {
+    base_url: String,
+    user_agent: String,
+    accept_language: Option<String>,
+    timeout: Option<Duration>,
+    max_retries: u32,
+    redirect_policy: RedirectPolicy,
+    proxy: Option<(String, u16)>,
+    max_response_bytes: usize,
+    allowed_redirect_hosts: Vec<String>,
+    max_idle_connections: usize,
+    default_search_by: SearchBy,
+    ssl: Option<Arc<Connect>>,
+}
+
+impl AurBuilder {
+    /// Start from the default configuration, as used by `Aur::new`.
+    pub fn new() -> AurBuilder {
+        AurBuilder {
+            base_url: AUR_BASE.to_owned(),
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            accept_language: None,
+            timeout: Some(Duration::from_secs(DEFAULT_TIMEOUT_SECS)),
+            max_retries: 0,
+            redirect_policy: RedirectPolicy::FollowAll,
+            proxy: None,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            allowed_redirect_hosts: Vec::new(),
+            max_idle_connections: DEFAULT_MAX_IDLE_CONNECTIONS,
+            default_search_by: SearchBy::NameDesc,
+            ssl: None,
+        }
+    }
+
+    /// Set the RPC endpoint. Defaults to the live AUR.
+    pub fn base_url(mut self, url: &str) -> AurBuilder {
+        self.base_url = url.to_owned();
+        self
+    }
+
+    /// Set the User-Agent. See `Aur::set_user_agent`.
+    pub fn user_agent(mut self, ua: &str) -> AurBuilder {
+        self.user_agent = ua.to_owned();
+        self
+    }
+
+    /// Set the Accept-Language. See `Aur::set_accept_language`.
+    pub fn accept_language(mut self, lang: &str) -> AurBuilder {
+        self.accept_language = Some(lang.to_owned());
+        self
+    }
+
+    /// Set the request timeout. See `Aur::set_timeout`.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> AurBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the number of retries. See `Aur::set_max_retries`.
+    pub fn max_retries(mut self, retries: u32) -> AurBuilder {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Choose whether to follow HTTP redirects. Defaults to true.
+    pub fn follow_redirects(mut self, follow: bool) -> AurBuilder {
+        self.redirect_policy = if follow {
+            RedirectPolicy::FollowAll
+        } else {
+            RedirectPolicy::FollowNone
+        };
+        self
+    }
+
+    /// Set how to handle HTTP redirects. See `Aur::set_redirect_policy`.
+    pub fn redirect_policy(mut self, policy: RedirectPolicy) -> AurBuilder {
+        self.redirect_policy = policy;
+        self
+    }
+
+    /// Restrict which hosts redirects may lead to. See `Aur::set_allowed_redirect_hosts`.
+    pub fn allowed_redirect_hosts(mut self, hosts: Vec<String>) -> AurBuilder {
+        self.allowed_redirect_hosts = hosts;
+        self
+    }
+
+    /// Set the response size limit. See `Aur::set_max_response_bytes`.
+    pub fn max_response_bytes(mut self, max: usize) -> AurBuilder {
+        self.max_response_bytes = max;
+        self
+    }
+
+    /// Set the field `Aur::search` matches against. See `Aur::set_default_search_by`.
+    pub fn default_search_by(mut self, by: SearchBy) -> AurBuilder {
+        self.default_search_by = by;
+        self
+    }
+
+    /// Set how many idle connections to keep open for reuse by later requests. Defaults to 5;
+    /// zero disables keep-alive.
+    pub fn max_idle_connections(mut self, max: usize) -> AurBuilder {
+        self.max_idle_connections = max;
+        self
+    }
+
+    /// Use `ssl` for HTTPS connections instead of OpenSSL's defaults.
+    ///
+    /// To trust a private CA (for a self-hosted mirror, say), build an `SslContext` that loads the
+    /// CA's certificate and enables peer verification, and pass `Openssl { context: .. }`. Any
+    /// other `Ssl` implementation works too.
+    pub fn ssl<S>(mut self, ssl: S) -> AurBuilder
+        where S: Ssl + Clone + fmt::Debug + Send + Sync + 'static,
+    {
+        self.ssl = Some(Arc::new(ssl));
+        self
+    }
+
+    /// Send all requests through the HTTP proxy at `host:port`.
+    ///
+    /// If no proxy is set, the `https_proxy` or `http_proxy` environment variable (matching the
+    /// base URL's scheme) is used instead, unless the AUR's host is listed in `no_proxy`.
+    pub fn proxy(mut self, host: &str, port: u16) -> AurBuilder {
+        self.proxy = Some((host.to_owned(), port));
+        self
+    }
+
+    /// Build the client, failing if the base URL is invalid.
+    pub fn build(self) -> Result<Aur, Error> {
+        let base = try!(Url::parse(&self.base_url));
+        let proxy = self.proxy.or_else(|| proxy_from_env(&base));
+        let ssl = self.ssl.unwrap_or_else(|| Arc::new(Openssl::default()));
+        let client = ssl.client(proxy.as_ref(), self.max_idle_connections);
+        let mut aur = Aur::with_client(client, base);
+        aur.proxy = proxy;
+        aur.user_agent = self.user_agent;
+        aur.accept_language = self.accept_language;
+        aur.max_retries = self.max_retries;
+        aur.max_response_bytes = self.max_response_bytes;
+        aur.redirect_policy = self.redirect_policy;
+        aur.allowed_redirect_hosts = self.allowed_redirect_hosts;
+        aur.default_search_by = self.default_search_by;
+        // We follow redirects ourselves so that they can be limited.
+        aur.client.set_redirect_policy(client::RedirectPolicy::FollowNone);
+        aur.set_timeout(self.timeout);
+        Ok(aur)
+    }
+}
+
+impl Default for AurBuilder {
+    fn default() -> AurBuilder {
+        AurBuilder::new()
+    }
+}
+
+/// A client that keeps up to `max_idle` connections from `connector` open for reuse.
+/// Builds clients whose HTTPS connections are secured by the `Ssl` given to `AurBuilder::ssl`.
+trait Connect: fmt::Debug + Send + Sync {
+    fn client(&self, proxy: Option<&(String, u16)>, max_idle: usize) -> Client;
+}
 
+impl<S> Connect for S
+    where S: Ssl + Clone + fmt::Debug + Send + Sync + 'static,
+{
+    fn client(&self, proxy: Option<&(String, u16)>, max_idle: usize) -> Client {
+        match proxy {
+            Some(&(ref host, port)) => {
+                pooled_client(ProxyConnector::new(host.clone(), port, self.clone()), max_idle)
+            },
+            None => pooled_client(HttpsConnector::new(self.clone()), max_idle),
+        }
+    }
+}
+
+fn pooled_client<C, S>(connector: C, max_idle: usize) -> Client
+    where C: NetworkConnector<Stream = S> + Send + Sync + 'static,
+          S: NetworkStream + Send,
+{
+    // hyper's pool panics on the second request to a host when it keeps no idle connections, so
+    // leave it out instead.
+    if max_idle == 0 {
+        Client::with_connector(connector)
+    } else {
+        Client::with_connector(Pool::with_connector(pool::Config { max_idle: max_idle }, connector))
+    }
+}
+
+/// Look up the proxy for `base` in the environment, the way curl does.
+fn proxy_from_env(base: &Url) -> Option<(String, u16)> {
+    let var = |name: &str| {
+        env::var(name.to_lowercase()).or_else(|_| env::var(name.to_uppercase()))
+            .ok()
+            .and_then(|v| if v.is_empty() { None } else { Some(v) })
+    };
+
+    let host = base.domain().unwrap_or("");
+    if let Some(no_proxy) = var("no_proxy") {
+        let excluded = no_proxy.split(',').map(str::trim).any(|pat| {
+            let pat = pat.trim_left_matches('.');
+            pat == "*" || host == pat || host.ends_with(&format!(".{}", pat))
+        });
+        if excluded {
+            return None;
+        }
+    }
+
+    let proxy = match var(&format!("{}_proxy", base.scheme)) {
+        Some(proxy) => proxy,
+        None => return None,
+    };
+    // Proxies are commonly given without a scheme.
+    let url = if proxy.contains("://") {
+        Url::parse(&proxy)
+    } else {
+        Url::parse(&format!("http://{}", proxy))
+    };
+    match url {
+        Ok(url) => match (url.serialize_host(), url.port_or_default()) {
+            (Some(host), Some(port)) => Some((host, port)),
+            _ => None,
+        },
+        Err(e) => {
+            warn!("ignoring invalid proxy {:?}: {}", proxy, e);
+            None
+        }
+    }
+}
+
+/// An AUR RPC client.
+///
+/// `Aur` is `Send` and `Sync`, so one client (and its connection pool) can be shared between
+/// threads by reference or through an `Arc` rather than cloned.
 pub struct Aur {
     client: Client,
     base: Url,
+    /// The query parameters sent with every RPC call.
+    base_query: Vec<(String, String)>,
+    user_agent: String,
+    accept_language: Option<String>,
+    timeout: Option<Duration>,
+    max_retries: u32,
+    redirect_policy: RedirectPolicy,
+    proxy: Option<(String, u16)>,
+    max_response_bytes: usize,
+    allowed_redirect_hosts: Vec<String>,
+    default_search_by: SearchBy,
+}
+
+#[allow(dead_code)]
+fn assert_aur_is_send_sync() {
+    fn check<T: Send + Sync>() {}
+    check::<Aur>();
 }
 
-// TODO HTTP2 Error?
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
+    /// Couldn't connect to the server.
+    Connect(io::Error),
+    /// The connection failed after it was established.
+    Read(io::Error),
     Ssl(Box<std::error::Error + Send + Sync>),
+    /// An HTTP/2 protocol error. Requests are made over HTTP/1.1 unless the client passed to
+    /// `Aur::with_client` speaks HTTP/2.
+    Http2(Box<std::error::Error + Send + Sync>),
     Utf8(std::str::Utf8Error),
+    /// Any other failure reported by hyper, such as a malformed response head.
+    Transport(String),
     Http {
         code: HttpStatus,
         message: String,
     },
     Aur(String),
+    Url(UrlError),
+    InvalidVersion(String),
+    /// A string that doesn't name a `SearchBy` field.
+    InvalidSearchBy(String),
+    /// A string that doesn't name a `SortBy` key.
+    InvalidSortBy(String),
+    EmptyArgument,
+    QueryTooShort,
+    TooManyResults,
     InvalidResponse,
+    NotFound,
+    Timeout,
+    /// The AUR asked us to slow down, and possibly how long to wait before trying again.
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    ResponseTooLarge,
+    /// The RPC answered with a web page instead of JSON, as it does during maintenance.
+    ServiceUnavailable,
+    /// The request was cancelled through `AsyncAur`.
+    Cancelled,
+    DisallowedRedirect {
+        host: String,
+    },
+    UnsupportedProtocol {
+        got: u64,
+    },
+    /// The response's `resultcount` doesn't match the number of results it contained.
+    IncompleteResponse {
+        expected: u64,
+        got: u64,
+    },
+    InvalidTimestamp {
+        field: &'static str,
+        value: String,
+    },
     Parse {
         code: ParseError,
         line: usize, 
         col: usize,
     },
+    /// A UTF-8 or parse error in the response from `url`.
+    Context {
+        url: String,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Returns true if the request that produced this error is worth retrying.
+    fn is_transient(&self) -> bool {
+        match *self {
+            Error::Io(_) | Error::Connect(_) | Error::Read(_) | Error::Timeout => true,
+            Error::RateLimited { .. } | Error::ServiceUnavailable => true,
+            Error::Http { ref code, .. } => code.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// Attach the URL of the response that caused a UTF-8 or parse error.
+    fn in_response(self, url: &Url) -> Error {
+        match self {
+            Error::Utf8(_) | Error::Parse { .. } => Error::Context {
+                url: url.serialize(),
+                error: Box::new(self),
+            },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Connect(ref e) => write!(f, "failed to connect: {}", e),
+            Error::Read(ref e) => write!(f, "connection failed: {}", e),
+            Error::Ssl(ref e) => write!(f, "SSL error: {}", e),
+            Error::Http2(ref e) => write!(f, "HTTP/2 error: {}", e),
+            Error::Utf8(ref e) => write!(f, "invalid UTF-8 in response: {}", e),
+            Error::Transport(ref msg) => write!(f, "HTTP transport error: {}", msg),
+            Error::Http { ref code, ref message } if message.is_empty() => write!(f, "HTTP error: {}", code),
+            Error::Http { ref code, ref message } => write!(f, "HTTP error: {}: {}", code, message),
+            Error::Aur(ref msg) => write!(f, "AUR error: {}", msg),
+            Error::Url(ref e) => write!(f, "invalid URL: {}", e),
+            Error::InvalidVersion(ref v) => write!(f, "invalid version: {:?}", v),
+            Error::InvalidSearchBy(ref s) => write!(f, "unknown search field: {:?}", s),
+            Error::InvalidSortBy(ref s) => write!(f, "unknown sort key: {:?}", s),
+            Error::EmptyArgument => write!(f, "empty argument"),
+            Error::QueryTooShort => {
+                write!(f, "search query must be at least {} bytes long", MIN_SEARCH_LEN)
+            },
+            Error::TooManyResults => write!(f, "too many results; try a more specific query"),
+            Error::InvalidResponse => write!(f, "invalid response from the AUR"),
+            Error::NotFound => write!(f, "not found"),
+            Error::Timeout => write!(f, "request timed out"),
+            Error::RateLimited { retry_after: Some(d) } => {
+                write!(f, "rate limited; retry after {} seconds", d.as_secs())
+            },
+            Error::RateLimited { retry_after: None } => write!(f, "rate limited"),
+            Error::ResponseTooLarge => write!(f, "response too large"),
+            Error::ServiceUnavailable => write!(f, "the AUR is unavailable (down for maintenance?)"),
+            Error::Cancelled => write!(f, "request cancelled"),
+            Error::DisallowedRedirect { ref host } => write!(f, "refusing to follow redirect to {}", host),
+            Error::UnsupportedProtocol { got } => {
+                write!(f, "unsupported RPC version {} (expected {})", got, RPC_VERSION)
+            },
+            Error::IncompleteResponse { expected, got } => {
+                write!(f, "incomplete response: expected {} results, got {}", expected, got)
+            },
+            Error::InvalidTimestamp { field, ref value } => {
+                write!(f, "invalid timestamp in {}: {}", field, value)
+            },
+            Error::Parse { ref code, line, col } => {
+                write!(f, "failed to parse response at line {}, column {}: {:?}", line, col, code)
+            },
+            Error::Context { ref url, ref error } => write!(f, "{} (from {})", error, url),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(std::error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) | Error::Connect(ref e) | Error::Read(ref e) => Some(e),
+            Error::Ssl(ref e) | Error::Http2(ref e) => Some(&**e),
+            Error::Utf8(ref e) => Some(e),
+            Error::Url(ref e) => Some(e),
+            Error::Context { ref error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
 }
 
 impl From<hyper::Error> for Error {
@@ -51,9 +545,12 @@ impl From<hyper::Error> for Error {
         use hyper::Error::*;
         match e {
             Utf8(e) => Error::Utf8(e),
-            Io(e) => Error::Io(e),
+            Io(e) => Error::from(e),
             Ssl(e) => Error::Ssl(e),
-            _ => panic!("BUG (in raur): unexpected error from hyper"),
+            Http2(e) => Error::Http2(Box::new(e)),
+            Uri(e) => Error::Url(e),
+            // Malformed responses (bad status lines, headers, etc.).
+            e => Error::Transport(e.to_string()),
         }
     }
 }
@@ -63,42 +560,481 @@ impl From<json::ParserError> for Error {
         use rustc_serialize::json::ParserError::*;
         match e {
             SyntaxError(e, l, c) => Error::Parse { code: e, line: l, col: c },
-            IoError(e) => Error::Io(e),
+            IoError(e) => Error::from(e),
         }
     }
 }
+impl From<UrlError> for Error {
+    fn from(e: UrlError) -> Self {
+        Error::Url(e)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
-        Error::Io(e)
+        match e.kind() {
+            // Socket timeouts surface as WouldBlock on unix.
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+            io::ErrorKind::ConnectionRefused |
+            io::ErrorKind::AddrNotAvailable |
+            io::ErrorKind::NotConnected => Error::Connect(e),
+            io::ErrorKind::ConnectionReset |
+            io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::BrokenPipe |
+            io::ErrorKind::UnexpectedEof => Error::Read(e),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+/// The field matched against by a search.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SearchBy {
+    /// Package name only.
+    Name,
+    /// Package name and description (the RPC default).
+    NameDesc,
+    /// Package maintainer.
+    Maintainer,
+    /// Package co-maintainers. Not supported by older servers.
+    CoMaintainers,
+    /// Packages that depend on the argument.
+    Depends,
+    /// Packages that make-depend on the argument.
+    MakeDepends,
+    /// Packages that optionally depend on the argument.
+    OptDepends,
+    /// Packages that check-depend on the argument.
+    CheckDepends,
+}
+
+impl SearchBy {
+    /// The value of the RPC's `by` parameter for this field.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SearchBy::Name => "name",
+            SearchBy::NameDesc => "name-desc",
+            SearchBy::Maintainer => "maintainer",
+            SearchBy::CoMaintainers => "comaintainers",
+            SearchBy::Depends => "depends",
+            SearchBy::MakeDepends => "makedepends",
+            SearchBy::OptDepends => "optdepends",
+            SearchBy::CheckDepends => "checkdepends",
+        }
+    }
+}
+
+impl fmt::Display for SearchBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SearchBy {
+    type Err = Error;
+
+    /// Parse the RPC's name for a field, as returned by `as_str`.
+    fn from_str(s: &str) -> Result<SearchBy, Error> {
+        Ok(match s {
+            "name" => SearchBy::Name,
+            "name-desc" => SearchBy::NameDesc,
+            "maintainer" => SearchBy::Maintainer,
+            "comaintainers" => SearchBy::CoMaintainers,
+            "depends" => SearchBy::Depends,
+            "makedepends" => SearchBy::MakeDepends,
+            "optdepends" => SearchBy::OptDepends,
+            "checkdepends" => SearchBy::CheckDepends,
+            _ => return Err(Error::InvalidSearchBy(s.to_owned())),
+        })
+    }
+}
+
+/// A key to rank packages by.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SortBy {
+    /// Most popular first.
+    Popularity,
+    /// Most votes first.
+    Votes,
+    /// Alphabetically by name, ignoring case, as `Package`'s `Ord` sorts.
+    Name,
+    /// Most recently modified first.
+    Modified,
+}
+
+impl SortBy {
+    /// The key's name, in lowercase.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SortBy::Popularity => "popularity",
+            SortBy::Votes => "votes",
+            SortBy::Name => "name",
+            SortBy::Modified => "modified",
+        }
+    }
+
+    /// Sort `pkgs` in place by this key.
+    pub fn sort(&self, pkgs: &mut [Package]) {
+        match *self {
+            SortBy::Popularity => pkgs.sort_by(|a, b| {
+                b.popularity.partial_cmp(&a.popularity).unwrap_or(Ordering::Equal)
+            }),
+            SortBy::Votes => pkgs.sort_by(|a, b| b.votes.cmp(&a.votes)),
+            SortBy::Name => pkgs.sort(),
+            SortBy::Modified => pkgs.sort_by(|a, b| b.modified.cmp(&a.modified)),
+        }
+    }
+}
+
+impl fmt::Display for SortBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SortBy {
+    type Err = Error;
+
+    /// Parse a key's name, as returned by `as_str`.
+    fn from_str(s: &str) -> Result<SortBy, Error> {
+        Ok(match s {
+            "popularity" => SortBy::Popularity,
+            "votes" => SortBy::Votes,
+            "name" => SortBy::Name,
+            "modified" => SortBy::Modified,
+            _ => return Err(Error::InvalidSortBy(s.to_owned())),
+        })
     }
 }
 
+/// A package category, as used by the AUR before RPC v5 dropped them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Category {
+    Daemons,
+    Devel,
+    Editors,
+    Emulators,
+    Games,
+    Gnome,
+    I18n,
+    Kde,
+    Lib,
+    Modules,
+    Multimedia,
+    Network,
+    Office,
+    Science,
+    System,
+    X11,
+    Xfce,
+    Kernels,
+    Fonts,
+}
+
+impl Category {
+    /// Look up a category by its AUR ID. ID 1 ("none") and unknown IDs give `None`.
+    pub fn from_id(id: u64) -> Option<Category> {
+        Some(match id {
+            2 => Category::Daemons,
+            3 => Category::Devel,
+            4 => Category::Editors,
+            5 => Category::Emulators,
+            6 => Category::Games,
+            7 => Category::Gnome,
+            8 => Category::I18n,
+            9 => Category::Kde,
+            10 => Category::Lib,
+            11 => Category::Modules,
+            12 => Category::Multimedia,
+            13 => Category::Network,
+            14 => Category::Office,
+            15 => Category::Science,
+            16 => Category::System,
+            17 => Category::X11,
+            18 => Category::Xfce,
+            19 => Category::Kernels,
+            20 => Category::Fonts,
+            _ => return None,
+        })
+    }
+
+    /// The category's name on the AUR.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Category::Daemons => "daemons",
+            Category::Devel => "devel",
+            Category::Editors => "editors",
+            Category::Emulators => "emulators",
+            Category::Games => "games",
+            Category::Gnome => "gnome",
+            Category::I18n => "i18n",
+            Category::Kde => "kde",
+            Category::Lib => "lib",
+            Category::Modules => "modules",
+            Category::Multimedia => "multimedia",
+            Category::Network => "network",
+            Category::Office => "office",
+            Category::Science => "science",
+            Category::System => "system",
+            Category::X11 => "x11",
+            Category::Xfce => "xfce",
+            Category::Kernels => "kernels",
+            Category::Fonts => "fonts",
+        }
+    }
+}
+
+/// A package on the AUR.
+///
+/// Packages are identified by their AUR `id`: two `Package`s with the same `id` compare equal and
+/// hash alike even if they were fetched at different times and their other fields differ.
+///
+/// Packages sort case-insensitively by name, falling back to `id` to keep the order stable.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Package {
+    #[cfg_attr(feature = "serde", serde(rename = "PackageBase"))]
     pub base_name: String,
+    #[cfg_attr(feature = "serde", serde(rename = "PackageBaseID"))]
     pub base_id: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "Name"))]
     pub name: String,
+    #[cfg_attr(feature = "serde", serde(rename = "Version"))]
     pub version: String,
-    pub homepage: String,
-    pub description: String,
-    pub out_of_date: bool,
+    /// The upstream URL, if the package has one.
+    #[cfg_attr(feature = "serde", serde(rename = "URL"))]
+    pub homepage: Option<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "Description"))]
+    pub description: Option<String>,
+    /// When the package was flagged out of date, if it has been.
+    #[cfg_attr(feature = "serde", serde(rename = "OutOfDate", with = "timestamp::option", default))]
+    pub out_of_date: Option<NaiveDateTime>,
 
+    #[cfg_attr(feature = "serde", serde(rename = "FirstSubmitted", with = "timestamp"))]
     pub created: NaiveDateTime,
+    #[cfg_attr(feature = "serde", serde(rename = "LastModified", with = "timestamp"))]
     pub modified: NaiveDateTime,
 
-    pub license: Option<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "License", default))]
+    pub license: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "Maintainer"))]
     pub maintainer: Option<String>,
+    /// Who first uploaded the package, if the server says.
+    #[cfg_attr(feature = "serde", serde(rename = "Submitter"))]
+    pub submitter: Option<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "NumVotes", default))]
     pub votes: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "Popularity", default))]
+    pub popularity: f64,
+    #[cfg_attr(feature = "serde", serde(rename = "ID"))]
     pub id: u64,
-    pub category_id: u64,
+    /// Only sent by servers older than RPC v5.
+    #[cfg_attr(feature = "serde", serde(rename = "CategoryID"))]
+    pub category_id: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(rename = "URLPath"))]
     pub download: String,
+
+    // Only populated by info requests; empty in search results, and may be missing when
+    // deserializing them.
+    #[cfg_attr(feature = "serde", serde(rename = "Depends", default))]
+    pub depends: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "MakeDepends", default))]
+    pub make_depends: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "CheckDepends", default))]
+    pub check_depends: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "OptDepends", default))]
+    pub opt_depends: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "Conflicts", default))]
+    pub conflicts: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "Provides", default))]
+    pub provides: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "Replaces", default))]
+    pub replaces: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "Groups", default))]
+    pub groups: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "Keywords", default))]
+    pub keywords: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "CoMaintainers", default))]
+    pub co_maintainers: Vec<String>,
+
+    /// When this client parsed the package (UTC), for judging staleness. This comes from the
+    /// local clock; the server doesn't send it. It isn't serialized, and deserializing sets it to
+    /// the current time.
+    #[cfg_attr(feature = "serde", serde(skip, default = "now"))]
+    pub fetched_at: NaiveDateTime,
+}
+
+impl PartialEq for Package {
+    fn eq(&self, other: &Package) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Package {}
+
+impl Ord for Package {
+    fn cmp(&self, other: &Package) -> Ordering {
+        if self.id == other.id {
+            return Ordering::Equal;
+        }
+        self.name.to_lowercase().cmp(&other.name.to_lowercase())
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+impl PartialOrd for Package {
+    fn partial_cmp(&self, other: &Package) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Package {
+    /// Format as a `pacman -Ss` style summary: `aur/name version (votes) [out-of-date]`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "aur/{} {} ({})", self.name, self.version, self.votes));
+        if self.is_out_of_date() {
+            try!(f.write_str(" [out-of-date]"));
+        }
+        Ok(())
+    }
+}
+
+impl Hash for Package {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
 }
 
 impl Package {
+    /// Returns true if the package has been flagged out of date.
+    pub fn is_out_of_date(&self) -> bool {
+        self.out_of_date.is_some()
+    }
+
+    /// Returns true if the package has no maintainer.
+    pub fn is_orphan(&self) -> bool {
+        self.maintainer.is_none()
+    }
+
+    /// How long ago the package was first submitted.
+    pub fn age(&self) -> Duration {
+        since(self.created)
+    }
+
+    /// How long the package has been flagged out of date, if it is.
+    pub fn staleness(&self) -> Option<Duration> {
+        self.out_of_date.map(since)
+    }
+
+    /// The package's category, if the server sent a known one.
+    pub fn category(&self) -> Option<Category> {
+        self.category_id.and_then(Category::from_id)
+    }
+
+    /// Parse the package's version for comparison.
+    pub fn parse_version(&self) -> Result<Version, Error> {
+        self.version.parse()
+    }
+
+    /// Returns true if the AUR has a newer version than `installed`, compared like `vercmp`.
+    ///
+    /// Fails with `Error::InvalidVersion` if either version can't be parsed.
+    pub fn needs_upgrade(&self, installed: &str) -> Result<bool, Error> {
+        let installed: Version = try!(installed.parse());
+        Ok(try!(self.parse_version()) > installed)
+    }
+
+    /// Resolve the snapshot download path against the AUR at `base` (see
+    /// `Aur::base_url`). Absolute download URLs are returned as-is.
+    pub fn download_url(&self, base: &Url) -> Result<Url, Error> {
+        Ok(try!(UrlParser::new().base_url(base).parse(&self.download)))
+    }
+
+    /// The package's `depends`, split into names and version bounds.
+    pub fn parsed_depends(&self) -> Vec<Dependency> {
+        self.depends.iter().map(|d| parse_dep(d)).collect()
+    }
+
+    /// The URL of the package's page on the live AUR website.
+    ///
+    /// Package names only contain characters that are safe in a URL path, so the name is used
+    /// as-is.
+    pub fn aur_web_url(&self) -> String {
+        format!("{}/packages/{}", AUR_WEB, self.name)
+    }
+
+    /// The URL of the package base's git repository on the AUR at `base`.
+    pub fn git_clone_url(&self, base: &Url) -> Result<Url, Error> {
+        Ok(try!(UrlParser::new().base_url(base).parse(&format!("/{}.git", self.base_name))))
+    }
+
+    /// The URL of a snapshot of the package base at `git_ref` (usually a commit hash) on the AUR
+    /// at `base`, rather than at its latest commit like `download_url`.
+    pub fn snapshot_url_for(&self, base: &Url, git_ref: &str) -> Result<Url, Error> {
+        let path = format!("/cgit/aur.git/snapshot/{}.tar.gz", self.base_name);
+        let mut url = try!(UrlParser::new().base_url(base).parse(&path));
+        url.set_query_from_pairs(vec![("h", &self.base_name[..]), ("id", git_ref)].into_iter());
+        Ok(url)
+    }
+
+    /// Encode the package as an object in the RPC's own format, which parses back into an equal
+    /// package. `fetched_at` isn't part of the format and is left out.
+    pub fn to_json(&self) -> Json {
+        fn string(s: &str) -> Json {
+            Json::String(s.to_owned())
+        }
+        fn optional(s: &Option<String>) -> Json {
+            s.as_ref().map(|s| string(s)).unwrap_or(Json::Null)
+        }
+        fn strings(v: &[String]) -> Json {
+            Json::Array(v.iter().map(|s| string(s)).collect())
+        }
+        fn secs(t: &NaiveDateTime) -> Json {
+            match t.timestamp() {
+                secs if secs >= 0 => Json::U64(secs as u64),
+                secs => Json::I64(secs),
+            }
+        }
+
+        let mut h = BTreeMap::new();
+        h.insert("ID".to_owned(), Json::U64(self.id));
+        h.insert("Name".to_owned(), string(&self.name));
+        h.insert("PackageBaseID".to_owned(), Json::U64(self.base_id));
+        h.insert("PackageBase".to_owned(), string(&self.base_name));
+        h.insert("Version".to_owned(), string(&self.version));
+        h.insert("Description".to_owned(), optional(&self.description));
+        h.insert("URL".to_owned(), optional(&self.homepage));
+        h.insert("NumVotes".to_owned(), Json::U64(self.votes));
+        h.insert("Popularity".to_owned(), Json::F64(self.popularity));
+        h.insert("OutOfDate".to_owned(), self.out_of_date.as_ref().map(secs).unwrap_or(Json::Null));
+        h.insert("Maintainer".to_owned(), optional(&self.maintainer));
+        h.insert("Submitter".to_owned(), optional(&self.submitter));
+        h.insert("FirstSubmitted".to_owned(), secs(&self.created));
+        h.insert("LastModified".to_owned(), secs(&self.modified));
+        h.insert("URLPath".to_owned(), string(&self.download));
+        if let Some(id) = self.category_id {
+            h.insert("CategoryID".to_owned(), Json::U64(id));
+        }
+        h.insert("License".to_owned(), strings(&self.license));
+        h.insert("Depends".to_owned(), strings(&self.depends));
+        h.insert("MakeDepends".to_owned(), strings(&self.make_depends));
+        h.insert("CheckDepends".to_owned(), strings(&self.check_depends));
+        h.insert("OptDepends".to_owned(), strings(&self.opt_depends));
+        h.insert("Conflicts".to_owned(), strings(&self.conflicts));
+        h.insert("Provides".to_owned(), strings(&self.provides));
+        h.insert("Replaces".to_owned(), strings(&self.replaces));
+        h.insert("Groups".to_owned(), strings(&self.groups));
+        h.insert("Keywords".to_owned(), strings(&self.keywords));
+        h.insert("CoMaintainers".to_owned(), strings(&self.co_maintainers));
+        Json::Object(h)
+    }
+
     fn from_json(j: Json) -> Result<Self, Error> {
         use rustc_serialize::json::Json::*;
         match j {
-            // TODO: Checked casts in timestamps.
             Json::Object(mut h) => Ok(Package {
                 base_name: match h.remove("PackageBase") {
                     Some(String(v)) => v,
@@ -106,6 +1042,8 @@ impl Package {
                 },
                 base_id: match h.remove("PackageBaseID") {
                     Some(U64(v)) => v,
+                    // Some mirrors send numbers as strings.
+                    Some(String(v)) => try!(v.parse().map_err(|_| Error::InvalidResponse)),
                     _ => return Err(Error::InvalidResponse),
                 },
                 name: match h.remove("Name") {
@@ -113,45 +1051,64 @@ impl Package {
                     _ => return Err(Error::InvalidResponse),
                 },
                 category_id: match h.remove("CategoryID") {
-                    Some(U64(v)) => v,
+                    Some(U64(v)) => Some(v),
+                    None => None,
                     _ => return Err(Error::InvalidResponse),
                 },
                 description: match h.remove("Description") {
-                    Some(String(v)) => v,
+                    Some(String(v)) => Some(v),
+                    Some(Null) | None => None,
                     _ => return Err(Error::InvalidResponse),
                 },
                 created: match h.remove("FirstSubmitted") {
-                    Some(U64(v)) if v <= (i64::MAX as u64) => NaiveDateTime::from_timestamp(v as i64, 0),
-                    _ => return Err(Error::InvalidResponse),
+                    Some(v) => try!(timestamp("FirstSubmitted", v)),
+                    None => return Err(Error::InvalidResponse),
                 },
                 modified: match h.remove("LastModified") {
-                    Some(U64(v)) if v <= (i64::MAX as u64) => NaiveDateTime::from_timestamp(v as i64, 0),
-                    _ => return Err(Error::InvalidResponse),
+                    Some(v) => try!(timestamp("LastModified", v)),
+                    None => return Err(Error::InvalidResponse),
                 },
                 id: match h.remove("ID") {
                     Some(U64(v)) => v,
+                    Some(String(v)) => try!(v.parse().map_err(|_| Error::InvalidResponse)),
                     _ => return Err(Error::InvalidResponse),
                 },
                 license: match h.remove("License") {
+                    Some(Null) | None => Vec::new(),
+                    Some(v) => try!(string_array(v)),
+                },
+                maintainer: match h.remove("Maintainer") {
                     Some(String(v)) => Some(v),
-                    Some(Null) => None,
+                    Some(Null) | None => None,
                     _ => return Err(Error::InvalidResponse),
                 },
-                maintainer: match h.remove("Maintainer") {
+                submitter: match h.remove("Submitter") {
                     Some(String(v)) => Some(v),
-                    Some(Null) => None,
+                    Some(Null) | None => None,
                     _ => return Err(Error::InvalidResponse),
                 },
                 votes: match h.remove("NumVotes") {
                     Some(U64(v)) => v,
+                    Some(String(v)) => try!(v.parse().map_err(|_| Error::InvalidResponse)),
+                    None => 0,
                     _ => return Err(Error::InvalidResponse),
                 },
-                out_of_date: match h.remove("OutOfDate") {
-                    Some(U64(v)) => v != 0,
+                popularity: match h.remove("Popularity") {
+                    Some(F64(v)) => v,
+                    Some(U64(v)) => v as f64,
+                    Some(I64(v)) => v as f64,
+                    None => 0.0,
                     _ => return Err(Error::InvalidResponse),
                 },
+                out_of_date: match h.remove("OutOfDate") {
+                    // Servers before RPC v5 send 0 instead of null.
+                    Some(Null) | Some(U64(0)) | None => None,
+                    Some(v) => Some(try!(timestamp("OutOfDate", v))),
+                },
                 homepage: match h.remove("URL") {
-                    Some(String(v)) => v,
+                    Some(String(ref v)) if v.is_empty() => None,
+                    Some(String(v)) => Some(v),
+                    Some(Null) | None => None,
                     _ => return Err(Error::InvalidResponse),
                 },
                 download: match h.remove("URLPath") {
@@ -162,6 +1119,47 @@ impl Package {
                     Some(String(v)) => v,
                     _ => return Err(Error::InvalidResponse),
                 },
+                depends: match h.remove("Depends") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                make_depends: match h.remove("MakeDepends") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                check_depends: match h.remove("CheckDepends") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                opt_depends: match h.remove("OptDepends") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                conflicts: match h.remove("Conflicts") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                provides: match h.remove("Provides") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                replaces: match h.remove("Replaces") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                groups: match h.remove("Groups") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                keywords: match h.remove("Keywords") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                co_maintainers: match h.remove("CoMaintainers") {
+                    Some(v) => try!(string_array(v)),
+                    None => Vec::new(),
+                },
+                fetched_at: now(),
             }),
             _ => {
                 debug!("Expected object, got: {:?}", j);
@@ -171,112 +1169,2710 @@ impl Package {
     }
 }
 
-impl Aur {
-    /// Create a new AUR client.
-    pub fn new() -> Aur {
-        let mut aur = Aur {
-            client: Client::new(),
-            base: Url::parse("https://aur4.archlinux.org/rpc.php").unwrap(),
+/// Just enough of a package to poll it for updates. See `Aur::summary`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PackageSummary {
+    #[cfg_attr(feature = "serde", serde(rename = "Name"))]
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(rename = "Version"))]
+    pub version: String,
+    #[cfg_attr(feature = "serde", serde(rename = "OutOfDate", with = "timestamp::option", default))]
+    pub out_of_date: Option<NaiveDateTime>,
+}
+
+impl PackageSummary {
+    /// Parse the summary's fields out of a package object, ignoring the rest.
+    fn from_json(j: Json) -> Result<Self, Error> {
+        let mut h = match j {
+            Json::Object(h) => h,
+            _ => return Err(Error::InvalidResponse),
         };
-        aur.client.set_redirect_policy(RedirectPolicy::FollowAll);
-        aur
+        Ok(PackageSummary {
+            name: match h.remove("Name") {
+                Some(Json::String(v)) => v,
+                _ => return Err(Error::InvalidResponse),
+            },
+            version: match h.remove("Version") {
+                Some(Json::String(v)) => v,
+                _ => return Err(Error::InvalidResponse),
+            },
+            out_of_date: match h.remove("OutOfDate") {
+                Some(Json::Null) | Some(Json::U64(0)) | None => None,
+                Some(v) => Some(try!(timestamp("OutOfDate", v))),
+            },
+        })
     }
+}
 
-    /// Search the AUR.
-    pub fn search(&self, pat: &str) -> Result<Vec<Package>, Error> {
-        match try!(self.call_one("search", pat)) {
-            Json::Array(a) => Ok(try!(a.into_iter().map(Package::from_json).collect())),
-            _ => Err(Error::InvalidResponse),
-        }
-    }
+/// Parse a Unix timestamp from the field `field`.
+fn timestamp(field: &'static str, v: Json) -> Result<NaiveDateTime, Error> {
+    let secs = match v {
+        Json::U64(secs) if secs <= i64::MAX as u64 => NaiveDateTime::from_timestamp_opt(secs as i64, 0),
+        Json::U64(_) | Json::I64(_) | Json::F64(_) => None,
+        _ => return Err(Error::InvalidResponse),
+    };
+    secs.ok_or_else(|| Error::InvalidTimestamp {
+        field: field,
+        value: v.to_string(),
+    })
+}
 
-    /// Search the AUR by maintainer.
-    pub fn msearch(&self, author: &str) -> Result<Vec<Package>, Error> {
-        match try!(self.call_one("msearch", author)) {
-            Json::Array(a) => Ok(try!(a.into_iter().map(Package::from_json).collect())),
+fn string_array(j: Json) -> Result<Vec<String>, Error> {
+    match j {
+        Json::Array(a) => a.into_iter().map(|v| match v {
+            Json::String(s) => Ok(s),
             _ => Err(Error::InvalidResponse),
+        }).collect(),
+        _ => {
+            debug!("Expected array, got: {:?}", j);
+            Err(Error::InvalidResponse)
         }
     }
+}
 
-    /// Retrieve information for the named package.
-    pub fn info(&self, name: &str) -> Result<Option<Package>, Error> {
-        let pkg = try!(self.call_one("info", name));
-        if pkg.as_array().map(|v|v.is_empty()).unwrap_or(false) {
-            Ok(None)
-        } else {
-            Package::from_json(pkg).map(|v|Some(v))
-        }
+/// Read a response body, undoing any gzip or deflate content encoding. Fails with
+/// `Error::ResponseTooLarge` once the decoded body is longer than `limit` bytes.
+fn read_body(response: Response, limit: usize) -> Result<Vec<u8>, Error> {
+    read_body_with(response, limit, |_| ())
+}
+
+/// Like `read_body`, calling `progress` with the number of bytes received so far (before
+/// decoding) after each read.
+fn read_body_with<F>(response: Response, limit: usize, progress: F) -> Result<Vec<u8>, Error>
+    where F: FnMut(usize),
+{
+    let encoding = response.headers.get::<ContentEncoding>().and_then(|e| e.last().cloned());
+    let mut response = BodyReader {
+        inner: response,
+        read: 0,
+        progress: progress,
+    };
+    let mut body = Vec::new();
+    match encoding {
+        Some(Encoding::Gzip) => try!(try!(GzDecoder::new(&mut response)).take(limit as u64 + 1).read_to_end(&mut body)),
+        Some(Encoding::Deflate) => try!(ZlibDecoder::new(&mut response).take(limit as u64 + 1).read_to_end(&mut body)),
+        _ => try!((&mut response).take(limit as u64 + 1).read_to_end(&mut body)),
+    };
+    if body.len() > limit {
+        return Err(Error::ResponseTooLarge);
+    }
+    // The decoders stop at the end of the compressed stream, which can leave the end of a
+    // chunked body unread. Hyper only reuses the connection once the body has been drained, but
+    // don't keep reading whatever a server sends after the stream.
+    if try!(io::copy(&mut (&mut response).take(limit as u64 + 1), &mut io::sink())) > limit as u64 {
+        return Err(Error::ResponseTooLarge);
     }
+    Ok(body)
+}
 
-    /// Retrieve information for the named packages.
+/// Reports progress reading a response, and fails reads once the `AsyncAur` query they're for
+/// has been cancelled. Dropping a response that hasn't been read to the end closes its
+/// connection.
+struct BodyReader<R, F> {
+    inner: R,
+    read: usize,
+    progress: F,
+}
+
+impl<R: Read, F: FnMut(usize)> Read for BodyReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if async_aur::is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Other, "request cancelled"));
+        }
+        let n = try!(self.inner.read(buf));
+        if n > 0 {
+            self.read += n;
+            (self.progress)(self.read);
+        }
+        Ok(n)
+    }
+}
+
+/// Reject HTML in place of JSON, which the AUR serves with a 200 while it's down for maintenance.
+fn expect_json(headers: &Headers, body: &[u8]) -> Result<(), Error> {
+    let html = match headers.get::<ContentType>() {
+        Some(&ContentType(Mime(TopLevel::Text, SubLevel::Html, _))) => true,
+        _ => body.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'<'),
+    };
+    if html {
+        Err(Error::ServiceUnavailable)
+    } else {
+        Ok(())
+    }
+}
+
+/// The current time in UTC.
+fn now() -> NaiveDateTime {
+    UTC::now().naive_utc()
+}
+
+/// The time elapsed since `t` (UTC), or zero if it's in the future.
+fn since(t: NaiveDateTime) -> Duration {
+    (now() - t).to_std().unwrap_or(Duration::from_secs(0))
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64
+}
+
+/// Strip any version constraint from a dependency, leaving the name.
+fn dep_name(dep: &str) -> &str {
+    match dep.find(|c| c == '<' || c == '>' || c == '=') {
+        Some(i) => &dep[..i],
+        None => dep,
+    }
+}
+
+/// Reject search terms the AUR would refuse. Like the AUR, only name and description searches
+/// have a minimum length, and it counts bytes rather than characters.
+fn check_search_term(pat: &str, by: SearchBy) -> Result<(), Error> {
+    let by_name = by == SearchBy::Name || by == SearchBy::NameDesc;
+    if pat.is_empty() {
+        Err(Error::EmptyArgument)
+    } else if by_name && pat.len() < MIN_SEARCH_LEN {
+        Err(Error::QueryTooShort)
+    } else {
+        Ok(())
+    }
+}
+
+/// Drop repeated arguments, keeping the first occurrence, and reject empty ones.
+fn dedup_args<'a, I>(args: I) -> Result<Vec<&'a str>, Error>
+    where I: IntoIterator<Item = &'a str>,
+{
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    for arg in args {
+        if arg.is_empty() {
+            return Err(Error::EmptyArgument);
+        }
+        if seen.insert(arg) {
+            out.push(arg);
+        }
+    }
+    Ok(out)
+}
+
+fn next_event<T>(parser: &mut Parser<T>) -> Result<JsonEvent, Error>
+    where T: Iterator<Item = char>,
+{
+    match parser.next() {
+        Some(JsonEvent::Error(e)) => Err(Error::from(e)),
+        Some(event) => Ok(event),
+        None => Err(Error::InvalidResponse),
+    }
+}
+
+/// Build the JSON value that starts with `event`, which has already been read from `parser`.
+fn build_json<T>(parser: &mut Parser<T>, event: JsonEvent) -> Result<Json, Error>
+    where T: Iterator<Item = char>,
+{
+    Ok(match event {
+        JsonEvent::NullValue => Json::Null,
+        JsonEvent::BooleanValue(v) => Json::Boolean(v),
+        JsonEvent::I64Value(v) => Json::I64(v),
+        JsonEvent::U64Value(v) => Json::U64(v),
+        JsonEvent::F64Value(v) => Json::F64(v),
+        JsonEvent::StringValue(v) => Json::String(v),
+        JsonEvent::ArrayStart => {
+            let mut array = Vec::new();
+            loop {
+                match try!(next_event(parser)) {
+                    JsonEvent::ArrayEnd => break,
+                    event => array.push(try!(build_json(parser, event))),
+                }
+            }
+            Json::Array(array)
+        },
+        JsonEvent::ObjectStart => {
+            let mut object = BTreeMap::new();
+            loop {
+                let event = try!(next_event(parser));
+                if let JsonEvent::ObjectEnd = event {
+                    break;
+                }
+                let key = try!(current_key(parser));
+                object.insert(key, try!(build_json(parser, event)));
+            }
+            Json::Object(object)
+        },
+        JsonEvent::Error(e) => return Err(Error::from(e)),
+        JsonEvent::ArrayEnd | JsonEvent::ObjectEnd => return Err(Error::InvalidResponse),
+    })
+}
+
+/// The key of the object member whose value the parser is currently reading.
+fn current_key<T>(parser: &Parser<T>) -> Result<String, Error>
+    where T: Iterator<Item = char>,
+{
+    match parser.stack().top() {
+        Some(StackElement::Key(k)) => Ok(k.to_owned()),
+        _ => Err(Error::InvalidResponse),
+    }
+}
+
+/// Map an error message from the AUR to the most specific `Error` we recognize.
+fn aur_error(msg: String) -> Error {
+    if msg.starts_with("Too many package results") {
+        Error::TooManyResults
+    } else {
+        Error::Aur(msg)
+    }
+}
+
+/// Parse an RPC response envelope, handing each element of `results` to `f` as soon as it has
+/// been read so that the full result set never exists as a single `Json` tree.
+///
+/// `body` is usually the characters of a response that has already been read into memory (it's
+/// checked for HTML and may be retried before it's parsed), so this doesn't save holding the raw
+/// body. What it saves is the tree: a `Json` object per result costs several times its text, and
+/// here only one result's tree exists at a time.
+fn parse_rpc<T, F>(body: T, mut f: F) -> Result<(), Error>
+    where T: Iterator<Item = char>,
+          F: FnMut(Json) -> Result<(), Error>,
+{
+    for result in try!(RpcResults::new(body)) {
+        try!(f(try!(result)));
+    }
+    Ok(())
+}
+
+#[derive(PartialEq)]
+enum RpcState {
+    Envelope,
+    Results,
+    Done,
+}
+
+/// Lazily walks an RPC response envelope, yielding the elements of `results` one at a time.
+///
+/// The rest of the envelope is checked once the results have been read, so an error reported by
+/// the AUR is yielded in place of (or after) the results, as is a `resultcount` that doesn't
+/// match them.
+struct RpcResults<T: Iterator<Item = char>> {
+    parser: Parser<T>,
+    state: RpcState,
+    typ: Option<Json>,
+    message: Option<Json>,
+    version: Option<u64>,
+    result_count: Option<u64>,
+    have_results: bool,
+    /// How many results have been read.
+    got: u64,
+}
+
+impl<T: Iterator<Item = char>> RpcResults<T> {
+    fn new(body: T) -> Result<RpcResults<T>, Error> {
+        let mut parser = Parser::new(body);
+        match try!(next_event(&mut parser)) {
+            JsonEvent::ObjectStart => (),
+            event => {
+                debug!("Got invalid response from server: {:?}", event);
+                return Err(Error::InvalidResponse);
+            }
+        }
+        let mut results = RpcResults {
+            parser: parser,
+            state: RpcState::Envelope,
+            typ: None,
+            message: None,
+            version: None,
+            result_count: None,
+            have_results: false,
+            got: 0,
+        };
+        try!(results.envelope());
+        Ok(results)
+    }
+
+    /// Read the envelope up to the start of the next `results` array, or to its end.
+    fn envelope(&mut self) -> Result<(), Error> {
+        loop {
+            let event = try!(next_event(&mut self.parser));
+            if let JsonEvent::ObjectEnd = event {
+                self.state = RpcState::Done;
+                return self.finish();
+            }
+            match (&try!(current_key(&self.parser))[..], event) {
+                ("results", JsonEvent::ArrayStart) => {
+                    self.have_results = true;
+                    self.state = RpcState::Results;
+                    return Ok(());
+                },
+                (key, event) => {
+                    let value = try!(build_json(&mut self.parser, event));
+                    match key {
+                        "type" => self.typ = Some(value),
+                        // Servers before RPC v5 don't send a version.
+                        "version" => match value {
+                            Json::U64(v) if v.to_string() == RPC_VERSION => self.version = Some(v),
+                            Json::U64(v) => return Err(Error::UnsupportedProtocol { got: v }),
+                            _ => return Err(Error::InvalidResponse),
+                        },
+                        "resultcount" => self.result_count = value.as_u64(),
+                        // RPC v5 reports errors in "error"; older versions used "results".
+                        "error" | "results" => self.message = Some(value),
+                        _ => (),
+                    }
+                },
+            }
+        }
+    }
+
+    /// Check the fully-read envelope.
+    fn finish(&mut self) -> Result<(), Error> {
+        match self.parser.next() {
+            None => (),
+            Some(JsonEvent::Error(e)) => return Err(Error::from(e)),
+            Some(_) => return Err(Error::InvalidResponse),
+        }
+
+        // Some proxies strip `type`. Without it, an error message or a results array decides.
+        let typ = self.typ.as_ref().and_then(|t| t.as_string());
+        let failed = match typ {
+            Some("error") => true,
+            None => self.message.is_some(),
+            Some(_) => false,
+        };
+        if failed {
+            return Err(aur_error(match self.message.take() {
+                Some(Json::String(s)) => s,
+                Some(m) => m.to_string(),
+                None => String::new(),
+            }));
+        }
+        if !self.have_results {
+            debug!("Got invalid response from server: type {:?}", self.typ);
+            return Err(Error::InvalidResponse);
+        }
+        match self.result_count {
+            Some(expected) if expected != self.got => Err(Error::IncompleteResponse {
+                expected: expected,
+                got: self.got,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T: Iterator<Item = char>> Iterator for RpcResults<T> {
+    type Item = Result<Json, Error>;
+
+    fn next(&mut self) -> Option<Result<Json, Error>> {
+        if self.state != RpcState::Results {
+            return None;
+        }
+        let result = match next_event(&mut self.parser) {
+            Ok(JsonEvent::ArrayEnd) => match self.envelope() {
+                Ok(()) => return self.next(),
+                Err(e) => Err(e),
+            },
+            Ok(event) => {
+                self.got += 1;
+                build_json(&mut self.parser, event)
+            },
+            Err(e) => Err(e),
+        };
+        // Syntax errors leave the parser in an unknown state, so stop there.
+        if result.is_err() {
+            self.state = RpcState::Done;
+        }
+        Some(result)
+    }
+}
+
+/// Owns a response body while handing out its characters.
+struct BodyChars {
+    body: String,
+    pos: usize,
+}
+
+impl Iterator for BodyChars {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.body[self.pos..].chars().next();
+        if let Some(c) = c {
+            self.pos += c.len_utf8();
+        }
+        c
+    }
+}
+
+/// A successful RPC response. See `Aur::raw_rpc`.
+#[derive(Clone, Debug)]
+pub struct RpcResponse {
+    pub status: HttpStatus,
+    pub headers: Headers,
+    /// The envelope's protocol `version`, if it has one.
+    pub version: Option<u64>,
+    /// The envelope's `resultcount`, if it has one.
+    pub result_count: Option<u64>,
+    /// The `results` array.
+    pub results: Json,
+}
+
+/// An iterator over the packages in an RPC response that has already been read, parsed as
+/// they're needed.
+///
+/// A package that fails to parse is yielded as an error without ending the iteration. Malformed
+/// JSON and errors reported by the AUR end it.
+pub struct Packages {
+    results: RpcResults<BodyChars>,
+    url: Url,
+}
+
+impl Iterator for Packages {
+    type Item = Result<Package, Error>;
+
+    fn next(&mut self) -> Option<Result<Package, Error>> {
+        let url = &self.url;
+        self.results.next().map(|r| {
+            r.and_then(Package::from_json).map_err(|e| e.in_response(url))
+        })
+    }
+}
+
+impl Aur {
+    /// Create a new AUR client with the default configuration.
+    pub fn new() -> Aur {
+        AurBuilder::new().build().unwrap()
+    }
+
+    /// Create a new AUR client talking to the RPC endpoint at `url`.
+    pub fn with_base_url(url: &str) -> Result<Aur, Error> {
+        AurBuilder::new().base_url(url).build()
+    }
+
+    /// Create a new AUR client using a preconfigured hyper `Client`.
+    ///
+    /// The client's redirect policy and timeouts are left as they are. Any redirects the client
+    /// doesn't follow itself are passed to `Aur::set_redirect_policy`, which defaults to
+    /// following all of them.
+    pub fn with_client(client: Client, base: Url) -> Aur {
+        // Keep any parameters the base URL carries; RPC calls only replace the version.
+        let mut base_query: Vec<(String, String)> = base.query_pairs()
+            .unwrap_or_else(Vec::new)
+            .into_iter()
+            .filter(|&(ref k, _)| k != "v")
+            .collect();
+        base_query.insert(0, ("v".to_owned(), RPC_VERSION.to_owned()));
+        Aur {
+            client: client,
+            base: base,
+            base_query: base_query,
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            accept_language: None,
+            timeout: None,
+            max_retries: 0,
+            redirect_policy: RedirectPolicy::FollowAll,
+            proxy: None,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            allowed_redirect_hosts: Vec::new(),
+            default_search_by: SearchBy::NameDesc,
+        }
+    }
+
+    /// Create a client that never touches the network, answering each request with the first of
+    /// `responses` whose query parameters (e.g. `type=info&arg[]=foo`) all appear in it. See the
+    /// `mock` module.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn from_responses<'a, I>(responses: I) -> Aur
+        where I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut client = Client::with_connector(mock::MockConnector::new(responses));
+        client.set_redirect_policy(client::RedirectPolicy::FollowNone);
+        Aur::with_client(client, Url::parse(AUR_BASE).unwrap())
+    }
+
+    /// The RPC endpoint this client talks to.
+    pub fn base_url(&self) -> &Url {
+        &self.base
+    }
+
+    /// The HTTP proxy requests are sent through, if any.
+    ///
+    /// Always `None` for clients created with `Aur::with_client`.
+    pub fn proxy(&self) -> Option<(&str, u16)> {
+        self.proxy.as_ref().map(|&(ref host, port)| (&host[..], port))
+    }
+
+    /// Set the User-Agent sent with every request.
+    ///
+    /// Tools built on this library should identify themselves here.
+    pub fn set_user_agent(&mut self, ua: &str) {
+        self.user_agent = ua.to_owned();
+    }
+
+    /// Set the Accept-Language sent with every request (e.g. `"de, en;q=0.5"`), so that the AUR
+    /// can localize its messages. `None`, the default, sends none and gets English.
+    pub fn set_accept_language(&mut self, lang: Option<&str>) {
+        self.accept_language = lang.map(str::to_owned);
+    }
+
+    /// Set how long to wait on a stalled connection before giving up with
+    /// `Error::Timeout`. `None` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.client.set_read_timeout(timeout);
+        self.client.set_write_timeout(timeout);
+    }
+
+    /// Set how many times to retry a request that failed with an I/O error, a
+    /// timeout, a server error, or rate limiting. Retries back off exponentially, or wait as
+    /// long as a rate-limited response's `Retry-After` asks, up to a minute; a longer wait fails
+    /// with `Error::RateLimited` right away.
+    ///
+    /// Defaults to zero (no retries).
+    pub fn set_max_retries(&mut self, retries: u32) {
+        self.max_retries = retries;
+    }
+
+    /// Set the field `Aur::search` (and the other searches built on it) matches against.
+    /// Defaults to `SearchBy::NameDesc`.
+    pub fn set_default_search_by(&mut self, by: SearchBy) {
+        self.default_search_by = by;
+    }
+
+    /// Only follow redirects to these hosts, failing with `Error::DisallowedRedirect` otherwise.
+    /// A leading `*.` matches any subdomain, so `*.archlinux.org` allows `aur.archlinux.org`.
+    /// Hosts are compared ignoring case.
+    ///
+    /// Defaults to empty, which allows any host.
+    pub fn set_allowed_redirect_hosts(&mut self, hosts: Vec<String>) {
+        self.allowed_redirect_hosts = hosts;
+    }
+
+    /// Returns true if the redirect allowlist permits `host`.
+    fn redirect_allowed(&self, host: &str) -> bool {
+        self.allowed_redirect_hosts.is_empty() || self.allowed_redirect_hosts.iter().any(|allowed| {
+            if allowed.starts_with("*.") {
+                host.to_lowercase().ends_with(&allowed[1..].to_lowercase())
+            } else {
+                host.eq_ignore_ascii_case(allowed)
+            }
+        })
+    }
+
+    /// Set the largest response body (after decompression) to accept before giving up with
+    /// `Error::ResponseTooLarge`. Defaults to 16 MiB.
+    pub fn set_max_response_bytes(&mut self, max: usize) {
+        self.max_response_bytes = max;
+    }
+
+    /// Set how to handle HTTP redirects. Defaults to `RedirectPolicy::FollowAll`.
+    pub fn set_redirect_policy(&mut self, policy: RedirectPolicy) {
+        self.redirect_policy = policy;
+    }
+
+    /// Search the AUR by the default field: name and description, unless changed with
+    /// `Aur::set_default_search_by`.
+    pub fn search(&self, pat: &str) -> Result<Vec<Package>, Error> {
+        self.search_by(pat, self.default_search_by)
+    }
+
+    /// Search the AUR, matching `pat` against the given field.
+    ///
+    /// Fails without making a request if `pat` is empty (with `Error::EmptyArgument`) or, for
+    /// name searches, shorter than the AUR allows (with `Error::QueryTooShort`).
+    pub fn search_by(&self, pat: &str, by: SearchBy) -> Result<Vec<Package>, Error> {
+        try!(check_search_term(pat, by));
+        self.call(&[("type", "search"), ("by", by.as_str()), ("arg", pat)])
+    }
+
+    /// Search the AUR like `search`, then look up the matches with `multiinfo` so that their
+    /// dependencies, keywords and so on are filled in. Results keep the search's order.
+    ///
+    /// Fails with `Error::TooManyResults` rather than making the follow-up requests if the search
+    /// matches more than 1000 packages.
+    pub fn search_full(&self, pat: &str) -> Result<Vec<Package>, Error> {
+        let found = try!(self.search(pat));
+        if found.len() > MAX_SEARCH_FULL {
+            return Err(Error::TooManyResults);
+        }
+        if found.is_empty() {
+            return Ok(found);
+        }
+        let mut full: HashMap<u64, Package> = try!(self.multiinfo(found.iter().map(|p| &p.name[..])))
+            .into_iter()
+            .map(|p| (p.id, p))
+            .collect();
+        // A package deleted between the two requests is left out.
+        Ok(found.iter().filter_map(|p| full.remove(&p.id)).collect())
+    }
+
+    /// Search the AUR like `search`, parsing the results lazily.
+    ///
+    /// The whole response is read before this returns, so the iterator doesn't hold a
+    /// connection open and stopping early doesn't save any download. What it saves is parsing:
+    /// packages that aren't asked for are never built.
+    pub fn search_iter(&self, pat: &str) -> Result<Packages, Error> {
+        try!(check_search_term(pat, self.default_search_by));
+        let url = self.query_url(vec![("type", "search"), ("by", self.default_search_by.as_str()), ("arg", pat)]);
+        let body = try!(self.fetch_json_body(url.clone()));
+        let results = try!(RpcResults::new(BodyChars { body: body, pos: 0 })
+                           .map_err(|e| e.in_response(&url)));
+        Ok(Packages {
+            results: results,
+            url: url,
+        })
+    }
+
+    /// Count the packages matching a search like `search`, without parsing them.
+    pub fn search_count(&self, pat: &str) -> Result<usize, Error> {
+        try!(check_search_term(pat, self.default_search_by));
+        let url = self.query_url(vec![("type", "search"), ("by", self.default_search_by.as_str()), ("arg", pat)]);
+        let body = try!(self.fetch_json_body(url.clone()));
+        let mut count = 0;
+        try!(parse_rpc(body.chars(), |_| {
+            count += 1;
+            Ok(())
+        }).map_err(|e| e.in_response(&url)));
+        Ok(count)
+    }
+
+    /// Search the AUR like `search`, returning the raw `results` JSON.
+    ///
+    /// This is meant for debugging responses that fail to parse as packages.
+    pub fn search_raw(&self, pat: &str) -> Result<Json, Error> {
+        try!(check_search_term(pat, self.default_search_by));
+        self.call_raw(&[("type", "search"), ("by", self.default_search_by.as_str()), ("arg", pat)])
+    }
+
+    /// Search the AUR like `search`, ranking the results by `sort`.
+    pub fn search_sorted(&self, pat: &str, sort: SortBy) -> Result<Vec<Package>, Error> {
+        let mut pkgs = try!(self.search(pat));
+        sort.sort(&mut pkgs);
+        Ok(pkgs)
+    }
+
+    /// Search the AUR like `search`, returning only the `limit` most popular results.
+    pub fn search_limited(&self, pat: &str, limit: usize) -> Result<Vec<Package>, Error> {
+        let mut pkgs = try!(self.search_sorted(pat, SortBy::Popularity));
+        pkgs.truncate(limit);
+        Ok(pkgs)
+    }
+
+    /// Search the AUR like `search`, returning only the `limit` most recently modified results,
+    /// newest first.
+    pub fn recently_updated(&self, pat: &str, limit: usize) -> Result<Vec<Package>, Error> {
+        let mut pkgs = try!(self.search_sorted(pat, SortBy::Modified));
+        pkgs.truncate(limit);
+        Ok(pkgs)
+    }
+
+    /// Search the AUR like `search`, keeping only the packages for which `f` returns true.
+    pub fn search_where<F>(&self, pat: &str, f: F) -> Result<Vec<Package>, Error>
+        where F: Fn(&Package) -> bool,
+    {
+        Ok(try!(self.search(pat)).into_iter().filter(|p| f(p)).collect())
+    }
+
+    /// Search the AUR like `search`, optionally hiding packages flagged out of date.
+    pub fn search_filtered(&self, pat: &str, include_out_of_date: bool) -> Result<Vec<Package>, Error> {
+        self.search_where(pat, |p| include_out_of_date || !p.is_out_of_date())
+    }
+
+    /// Search the AUR by maintainer.
+    pub fn msearch(&self, author: &str) -> Result<Vec<Package>, Error> {
+        self.call_one("msearch", author)
+    }
+
+    /// Retrieve the packages maintained by `user`, grouped by package base.
+    pub fn packages_by_maintainer(&self, user: &str) -> Result<HashMap<String, Vec<Package>>, Error> {
+        let mut bases = HashMap::new();
+        for pkg in try!(self.search_by(user, SearchBy::Maintainer)) {
+            bases.entry(pkg.base_name.clone()).or_insert_with(Vec::new).push(pkg);
+        }
+        Ok(bases)
+    }
+
+    /// Retrieve the packages `user` maintains or co-maintains, each listed once.
+    pub fn packages_involving(&self, user: &str) -> Result<Vec<Package>, Error> {
+        let mut pkgs = try!(self.search_by(user, SearchBy::Maintainer));
+        let mut seen: HashSet<u64> = pkgs.iter().map(|p| p.id).collect();
+        for pkg in try!(self.search_by(user, SearchBy::CoMaintainers)) {
+            if seen.insert(pkg.id) {
+                pkgs.push(pkg);
+            }
+        }
+        Ok(pkgs)
+    }
+
+    /// Retrieve information for the named package, or `None` if there's no such package.
+    ///
+    /// Fails with `Error::EmptyArgument` if `name` is empty.
+    pub fn info(&self, name: &str) -> Result<Option<Package>, Error> {
+        Ok(try!(self.info_all(name)).into_iter().next())
+    }
+
+    /// Retrieve the name, version and out-of-date flag of the named package, or `None` if
+    /// there's no such package.
+    ///
+    /// This skips parsing the rest of the package, for programs that poll one often.
+    pub fn summary(&self, name: &str) -> Result<Option<PackageSummary>, Error> {
+        if name.is_empty() {
+            return Err(Error::EmptyArgument);
+        }
+        let url = self.multi_url("info", iter::once(name));
+        let body = try!(self.fetch_json_body(url.clone()));
+        let mut summary = None;
+        try!(parse_rpc(body.chars(), |result| {
+            if summary.is_none() {
+                summary = Some(try!(PackageSummary::from_json(result)));
+            }
+            Ok(())
+        }).map_err(|e| e.in_response(&url)));
+        Ok(summary)
+    }
+
+    /// Retrieve `name` and, recursively, every AUR package it depends or make-depends on.
+    ///
+    /// Dependencies are looked up by name, so those that aren't AUR packages (or that are only
+    /// satisfied by some package's `provides`) are skipped. Each package appears once, with
+    /// `name` first if it exists.
+    pub fn resolve_deps(&self, name: &str) -> Result<Vec<Package>, Error> {
+        let mut seen = HashSet::new();
+        seen.insert(name.to_owned());
+        let mut queue = vec![name.to_owned()];
+        let mut pkgs = Vec::new();
+        while !queue.is_empty() {
+            let found = try!(self.multiinfo(queue.iter().map(|n| &n[..])));
+            queue.clear();
+            for pkg in found {
+                for dep in pkg.depends.iter().chain(&pkg.make_depends) {
+                    let dep = dep_name(dep);
+                    if seen.insert(dep.to_owned()) {
+                        queue.push(dep.to_owned());
+                    }
+                }
+                pkgs.push(pkg);
+            }
+        }
+        Ok(pkgs)
+    }
+
+    /// Retrieve every result the RPC returns for `name`.
+    ///
+    /// This uses the `arg[]` form of the query, as the single-argument `info` form is deprecated.
+    pub fn info_all(&self, name: &str) -> Result<Vec<Package>, Error> {
+        if name.is_empty() {
+            return Err(Error::EmptyArgument);
+        }
+        self.call_multi("info", iter::once(name))
+    }
+
+    /// Retrieve information for the named package, returning the raw `results` JSON.
+    ///
+    /// This is meant for debugging responses that fail to parse as packages.
+    pub fn info_raw(&self, name: &str) -> Result<Json, Error> {
+        if name.is_empty() {
+            return Err(Error::EmptyArgument);
+        }
+        self.rpc_raw(self.multi_url("info", iter::once(name)))
+    }
+
+    /// Retrieve information for every package built from the package base `base`.
+    ///
+    /// The RPC can't query by package base, so this reads the names the base builds from its
+    /// `.SRCINFO` and looks them up with `multiinfo`. A base that doesn't exist has no packages.
+    pub fn info_by_base(&self, base: &str) -> Result<Vec<Package>, Error> {
+        if base.is_empty() {
+            return Err(Error::EmptyArgument);
+        }
+        let info: SrcInfo = match self.srcinfo(base) {
+            Ok(text) => try!(text.parse()),
+            Err(Error::NotFound) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        if info.pkgnames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let pkgs = try!(self.multiinfo(info.pkgnames.iter().map(|n| &n[..])));
+        Ok(pkgs.into_iter().filter(|p| p.base_name == base).collect())
+    }
+
+    /// Retrieve information for the named packages.
+    ///
+    /// Duplicate names are only requested once, and large requests are split into several RPC
+    /// calls. Fails with `Error::EmptyArgument` if any name is empty.
     pub fn multiinfo<'a, I>(&self, names: I) -> Result<Vec<Package>, Error>
         where I: IntoIterator<Item = &'a str>,
     {
-        match try!(self.call_multi("multiinfo", names)) {
-            Json::Array(a) => Ok(try!(a.into_iter().map(Package::from_json).collect())),
-            _ => Err(Error::InvalidResponse),
+        let names = try!(dedup_args(names));
+        let mut pkgs = Vec::new();
+        for chunk in self.chunk_args("multiinfo", names) {
+            pkgs.extend(try!(self.call_multi("multiinfo", chunk)));
         }
+        Ok(pkgs)
     }
 
-    fn call_one(&self, fun: &str, arg: &str) -> Result<Json, Error> {
-        let mut url = self.base.clone();
-        url.set_query_from_pairs([("type", fun), ("arg", arg)].into_iter().cloned());
-        self.rpc(url)
-    }
-    
-    fn call_multi<'a, I>(&self, fun: &'a str, args: I) -> Result<Json, Error>
+    /// Like `multiinfo`, but a package that fails to parse is returned as an error alongside the
+    /// rest instead of failing the whole request. Malformed JSON and errors reported by the AUR
+    /// still fail it.
+    pub fn multiinfo_lenient<'a, I>(&self, names: I) -> Result<(Vec<Package>, Vec<Error>), Error>
         where I: IntoIterator<Item = &'a str>,
     {
-        let mut url = self.base.clone();
-        let iter = iter::once(("type", fun)).chain(iter::repeat("arg[]").zip(args.into_iter()));
-        url.set_query_from_pairs(iter);
-        self.rpc(url)
+        let names = try!(dedup_args(names));
+        let mut pkgs = Vec::new();
+        let mut errors = Vec::new();
+        for chunk in self.chunk_args("multiinfo", names) {
+            let url = self.multi_url("multiinfo", chunk);
+            let body = try!(self.fetch_json_body(url.clone()));
+            try!(parse_rpc(body.chars(), |result| {
+                match Package::from_json(result) {
+                    Ok(pkg) => pkgs.push(pkg),
+                    Err(e) => errors.push(e.in_response(&url)),
+                }
+                Ok(())
+            }).map_err(|e| e.in_response(&url)));
+        }
+        Ok((pkgs, errors))
     }
 
-    fn rpc(&self, url: Url) -> Result<Json, Error> {
-        let mut response = try!(self.client.get(url).send());
-        if !response.status.is_success() {
-            let mut msg = if let Some(&hyper::header::ContentLength(len)) = response.headers.get() {
-                // TODO: Safe cast?
-                String::with_capacity(len as usize)
-            } else {
-                String::new()
-            };
-            try!(response.read_to_string(&mut msg));
-            return Err(Error::Http {
-                code: response.status,
-                message: msg
-            })
+    /// Like `multiinfo`, but issue up to `concurrency` of the chunked requests at once.
+    ///
+    /// If any request fails, no further requests are started and the error from the earliest
+    /// failed chunk is returned.
+    pub fn multiinfo_parallel<'a, I>(&self, names: I, concurrency: usize) -> Result<Vec<Package>, Error>
+        where I: IntoIterator<Item = &'a str>,
+    {
+        let chunks = self.chunk_args("multiinfo", try!(dedup_args(names)));
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let results = Mutex::new(Vec::with_capacity(chunks.len()));
+        // Let an `AsyncAur` future cancel the workers along with this thread.
+        let cancelled = async_aur::cancel_flag();
+
+        thread::scope(|scope| {
+            for _ in 0..cmp::max(1, cmp::min(concurrency, chunks.len())) {
+                scope.spawn(|| {
+                    async_aur::set_cancel_flag(cancelled.clone());
+                    while !failed.load(AtomicOrdering::SeqCst) {
+                        let i = next.fetch_add(1, AtomicOrdering::SeqCst);
+                        let chunk = match chunks.get(i) {
+                            Some(chunk) => chunk,
+                            None => break,
+                        };
+                        let result = self.call_multi("multiinfo", chunk.iter().cloned());
+                        if result.is_err() {
+                            failed.store(true, AtomicOrdering::SeqCst);
+                        }
+                        results.lock().unwrap().push((i, result));
+                    }
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|&(i, _)| i);
+        let mut pkgs = Vec::new();
+        for (_, result) in results {
+            pkgs.extend(try!(result));
         }
+        Ok(pkgs)
+    }
 
-        let mut obj = match try!(Json::from_reader(&mut response)) {
-            Json::Object(h) => h,
-            other => {
-                debug!("Got invalid response from server: {:?}", other);
-                return Err(Error::InvalidResponse);
+    /// Check which of the named packages exist in the AUR.
+    ///
+    /// The map is keyed by the names exactly as given; they're matched case-insensitively.
+    pub fn exists<'a, I>(&self, names: I) -> Result<HashMap<String, bool>, Error>
+        where I: IntoIterator<Item = &'a str>,
+    {
+        let names: Vec<&str> = names.into_iter().collect();
+        let found: HashSet<String> = try!(self.multiinfo(names.iter().cloned()))
+            .into_iter()
+            .map(|p| p.name.to_lowercase())
+            .collect();
+        Ok(names.into_iter()
+           .map(|n| (n.to_owned(), found.contains(&n.to_lowercase())))
+           .collect())
+    }
+
+    /// Retrieve the current version of each of the named packages, keyed by package name.
+    /// Packages that don't exist are left out.
+    pub fn versions<'a, I>(&self, names: I) -> Result<HashMap<String, String>, Error>
+        where I: IntoIterator<Item = &'a str>,
+    {
+        Ok(try!(self.multiinfo(names))
+           .into_iter()
+           .map(|p| (p.name, p.version))
+           .collect())
+    }
+
+    /// Check installed packages, given as name/version pairs, for upgrades.
+    ///
+    /// Returns each package found in the AUR paired with whether it's newer than the installed
+    /// version, followed by the names that weren't found.
+    pub fn check_upgrades(&self, installed: &[(String, String)])
+                          -> Result<(Vec<(Package, bool)>, Vec<String>), Error>
+    {
+        let found: HashMap<String, Package> = try!(self.multiinfo(installed.iter().map(|&(ref n, _)| &n[..])))
+            .into_iter()
+            .map(|p| (p.name.to_lowercase(), p))
+            .collect();
+        let mut upgrades = Vec::new();
+        let mut missing = Vec::new();
+        for &(ref name, ref version) in installed {
+            match found.get(&name.to_lowercase()) {
+                Some(pkg) => {
+                    let newer = try!(pkg.needs_upgrade(version));
+                    upgrades.push((pkg.clone(), newer));
+                },
+                None => missing.push(name.clone()),
             }
+        }
+        Ok((upgrades, missing))
+    }
+
+    /// Check that the AUR is reachable, with a small `suggest` request whose answer is ignored.
+    /// Fails if the request does, including with a non-success status.
+    pub fn ping(&self) -> Result<(), Error> {
+        self.fetch_bytes(self.query_url(vec![("type", "suggest"), ("arg", "a")])).map(|_| ())
+    }
+
+    /// Suggest package names starting with `pat`.
+    pub fn suggest(&self, pat: &str) -> Result<Vec<String>, Error> {
+        self.call_suggest("suggest", pat)
+    }
+
+    /// Suggest package base names starting with `pat`.
+    pub fn suggest_pkgbase(&self, pat: &str) -> Result<Vec<String>, Error> {
+        self.call_suggest("suggest-pkgbase", pat)
+    }
+
+    // Suggestions come back as a bare array, not wrapped in the usual envelope.
+    fn call_suggest(&self, fun: &str, arg: &str) -> Result<Vec<String>, Error> {
+        string_array(try!(self.fetch_json(self.query_url(vec![("type", fun), ("arg", arg)]))))
+    }
+
+    /// Issue an RPC request with arbitrary query parameters, returning the raw `results` JSON.
+    ///
+    /// The RPC version parameter is added automatically. This is an escape hatch for parameters
+    /// this crate doesn't support yet.
+    pub fn call_raw(&self, params: &[(&str, &str)]) -> Result<Json, Error> {
+        self.rpc_raw(self.query_url(params.iter().cloned()))
+    }
+
+    /// Issue an RPC request to `url`, returning the response's status and headers along with the
+    /// raw `results` JSON.
+    pub fn raw_rpc(&self, url: Url) -> Result<RpcResponse, Error> {
+        let (status, headers, body) = try!(self.fetch_with(url.clone(), expect_json));
+        let body = try!(String::from_utf8(body)
+                        .map_err(|e| Error::Utf8(e.utf8_error()).in_response(&url)));
+        let parse = || {
+            let mut envelope = try!(RpcResults::new(body.chars()));
+            let results = try!((&mut envelope).collect());
+            Ok((envelope.version, envelope.result_count, results))
         };
+        let (version, result_count, results) = try!(parse().map_err(|e: Error| e.in_response(&url)));
+        Ok(RpcResponse {
+            status: status,
+            headers: headers,
+            version: version,
+            result_count: result_count,
+            results: Json::Array(results),
+        })
+    }
 
-        let (typ, result) = if let (Some(t), Some(r)) = (obj.remove("type"), obj.remove("results")) {
-            (t, r)
-        } else {
-            debug!("Got invalid response from server: {:?}", obj);
-            return Err(Error::InvalidResponse);
+    /// Fetch the PKGBUILD for the named package base.
+    pub fn pkgbuild(&self, pkgbase: &str) -> Result<String, Error> {
+        self.cgit_file("PKGBUILD", pkgbase)
+    }
+
+    /// Fetch the `.SRCINFO` for the named package base. It can be parsed into a `SrcInfo`.
+    pub fn srcinfo(&self, pkgbase: &str) -> Result<String, Error> {
+        self.cgit_file(".SRCINFO", pkgbase)
+    }
+
+    /// Download the snapshot tarball for `pkg`.
+    ///
+    /// Fails with `Error::NotFound` if the snapshot has been removed.
+    pub fn download(&self, pkg: &Package) -> Result<Vec<u8>, Error> {
+        match self.fetch_bytes(try!(pkg.download_url(&self.base))) {
+            Err(Error::Http { code: HttpStatus::NotFound, .. }) => Err(Error::NotFound),
+            r => r,
+        }
+    }
+
+    /// Like `download`, but calls `on_chunk` as the snapshot arrives with the number of bytes
+    /// received so far and, if the server sent a `Content-Length`, the total.
+    ///
+    /// Unlike `download`, failed downloads aren't retried.
+    pub fn download_with_progress<F>(&self, pkg: &Package, mut on_chunk: F) -> Result<Vec<u8>, Error>
+        where F: FnMut(usize, Option<usize>),
+    {
+        let response = match self.get(try!(pkg.download_url(&self.base))) {
+            Err(Error::Http { code: HttpStatus::NotFound, .. }) => return Err(Error::NotFound),
+            r => try!(r),
         };
+        let total = response.headers.get::<ContentLength>().map(|&ContentLength(len)| len as usize);
+        read_body_with(response, self.max_response_bytes, |read| on_chunk(read, total))
+    }
 
-        return match typ.as_string() {
-            Some("error") => Err(Error::Aur(match result {
-                Json::String(s) => s,
-                r => r.to_string(),
-            })),
-            None => {
-                debug!("Bad type from server: {:?}", typ);
-                Err(Error::InvalidResponse)
-            },
-            _ => {
-                trace!("{:#?}", result);
-                Ok(result)
+    /// List the name of every package in the AUR, from the `packages.gz` dump the AUR publishes
+    /// alongside the RPC.
+    pub fn all_package_names(&self) -> Result<Vec<String>, Error> {
+        let url = try!(UrlParser::new().base_url(&self.base).parse("/packages.gz"));
+        let raw = try!(self.fetch_bytes(url.clone()));
+        // The body may already have been decompressed if it was sent with a gzip
+        // Content-Encoding.
+        let list = if raw.starts_with(&[0x1f, 0x8b]) {
+            let mut list = Vec::new();
+            try!(try!(GzDecoder::new(&raw[..]))
+                 .take(self.max_response_bytes as u64 + 1)
+                 .read_to_end(&mut list));
+            if list.len() > self.max_response_bytes {
+                return Err(Error::ResponseTooLarge);
             }
+            list
+        } else {
+            raw
+        };
+        let list = try!(String::from_utf8(list)
+                        .map_err(|e| Error::Utf8(e.utf8_error()).in_response(&url)));
+        // The first line is a comment saying when the list was generated.
+        Ok(list.lines()
+           .filter(|l| !l.is_empty() && !l.starts_with('#'))
+           .map(str::to_owned)
+           .collect())
+    }
+
+    /// Fetch the comments on a package's AUR page, newest first after any pinned ones.
+    ///
+    /// The page only shows the latest comments, so older ones aren't returned. Fails with
+    /// `Error::NotFound` if there's no such package.
+    pub fn comments(&self, name: &str) -> Result<Vec<Comment>, Error> {
+        let url = try!(UrlParser::new().base_url(&self.base).parse(&format!("/packages/{}", name)));
+        match self.fetch_body(url) {
+            Ok(html) => Ok(comments::parse_comments(&html)),
+            Err(Error::Http { code: HttpStatus::NotFound, .. }) => Err(Error::NotFound),
+            Err(e) => Err(e),
         }
     }
+
+    /// Fetch a file from the head of a package base's git repository.
+    fn cgit_file(&self, file: &str, pkgbase: &str) -> Result<String, Error> {
+        let path = format!("/cgit/aur.git/plain/{}", file);
+        let mut url = try!(UrlParser::new().base_url(&self.base).parse(&path));
+        url.set_query_from_pairs(iter::once(("h", pkgbase)));
+
+        match self.fetch_body(url) {
+            Err(Error::Http { code: HttpStatus::NotFound, .. }) => Err(Error::NotFound),
+            r => r,
+        }
+    }
+
+    /// Make a single-argument call, failing with `Error::EmptyArgument` if `arg` is empty.
+    fn call_one(&self, fun: &str, arg: &str) -> Result<Vec<Package>, Error> {
+        if arg.is_empty() {
+            return Err(Error::EmptyArgument);
+        }
+        self.call(&[("type", fun), ("arg", arg)])
+    }
+
+    fn call(&self, params: &[(&str, &str)]) -> Result<Vec<Package>, Error> {
+        self.rpc(self.query_url(params.iter().cloned()))
+    }
+
+    fn call_multi<'a, I>(&self, fun: &'a str, args: I) -> Result<Vec<Package>, Error>
+        where I: IntoIterator<Item = &'a str>,
+    {
+        self.rpc(self.multi_url(fun, args))
+    }
+
+    fn multi_url<'a, I>(&self, fun: &'a str, args: I) -> Url
+        where I: IntoIterator<Item = &'a str>,
+    {
+        self.query_url(iter::once(("type", fun)).chain(iter::repeat("arg[]").zip(args.into_iter())))
+    }
+
+    /// Split `args` into batches small enough to keep each `fun` request URL under
+    /// `MAX_URL_LEN`.
+    fn chunk_args<'a, I>(&self, fun: &str, args: I) -> Vec<Vec<&'a str>>
+        where I: IntoIterator<Item = &'a str>,
+    {
+        let base_len = self.query_url(vec![("type", fun)]).serialize().len();
+        let mut chunks = Vec::new();
+        let mut chunk = Vec::new();
+        let mut len = base_len;
+        for arg in args {
+            // +1 for the separating '&'.
+            let arg_len = form_urlencoded::serialize(&[("arg[]", arg)]).len() + 1;
+            if !chunk.is_empty() && len + arg_len > MAX_URL_LEN {
+                chunks.push(mem::replace(&mut chunk, Vec::new()));
+                len = base_len;
+            }
+            chunk.push(arg);
+            len += arg_len;
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    /// Build an RPC URL from `params`.
+    ///
+    /// `set_query_from_pairs` form-encodes every key and value, so reserved characters in
+    /// package names survive the trip: `gtk+` is sent as `gtk%2B` (a bare `+` would be read as
+    /// a space), `#` as `%23`, and non-ASCII names as percent-encoded UTF-8. It also replaces
+    /// the whole query, so each URL only carries `base_query` and `params`.
+    fn query_url<'a, I>(&self, params: I) -> Url
+        where I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut url = self.base.clone();
+        url.set_query_from_pairs(self.base_query.iter()
+                                 .map(|&(ref k, ref v)| (&k[..], &v[..]))
+                                 .chain(params.into_iter().map(|(k, v)| (k, v))));
+        url
+    }
+
+    /// Issue a GET request, following redirects as allowed by the redirect policy and turning
+    /// non-success statuses into errors.
+    fn get(&self, mut url: Url) -> Result<Response, Error> {
+        let mut redirects = 0;
+        let response = loop {
+            let mut headers = Headers::new();
+            headers.set(UserAgent(self.user_agent.clone()));
+            headers.set(AcceptEncoding(vec![qitem(Encoding::Gzip), qitem(Encoding::Deflate)]));
+            if let Some(ref lang) = self.accept_language {
+                headers.set_raw("Accept-Language", vec![lang.clone().into_bytes()]);
+            }
+            let response = try!(self.client.get(url.clone()).headers(headers).send());
+            if !response.status.is_redirection() || !self.redirect_policy.allows(redirects) {
+                break response;
+            }
+            let next = match response.headers.get::<Location>() {
+                Some(&Location(ref loc)) => try!(UrlParser::new().base_url(&url).parse(loc)),
+                None => break response,
+            };
+            let host = next.serialize_host().unwrap_or_default();
+            if !self.redirect_allowed(&host) {
+                return Err(Error::DisallowedRedirect { host: host });
+            }
+            debug!("Following redirect from {} to {}", url, next);
+            url = next;
+            redirects += 1;
+        };
+        if response.status == HttpStatus::TooManyRequests {
+            // Only the delay-seconds form of Retry-After is understood.
+            let retry_after = response.headers.get_raw("Retry-After")
+                .and_then(|v| v.first())
+                .and_then(|v| std::str::from_utf8(v).ok())
+                .and_then(|v| v.trim().parse().ok())
+                .map(Duration::from_secs);
+            return Err(Error::RateLimited { retry_after: retry_after });
+        }
+        if !response.status.is_success() {
+            let code = response.status;
+            let msg = String::from_utf8_lossy(&try!(read_body(response, self.max_response_bytes))).into_owned();
+            return Err(Error::Http {
+                code: code,
+                message: msg
+            })
+        }
+        Ok(response)
+    }
+
+    /// Fetch and parse a JSON document without looking for the RPC envelope.
+    fn fetch_json(&self, url: Url) -> Result<Json, Error> {
+        let body = try!(self.fetch_json_body(url.clone()));
+        Json::from_str(&body).map_err(|e| Error::from(e).in_response(&url))
+    }
+
+    /// Fetch a response body as text, retrying transient failures.
+    fn fetch_body(&self, url: Url) -> Result<String, Error> {
+        let body = try!(self.fetch_bytes(url.clone()));
+        String::from_utf8(body).map_err(|e| Error::Utf8(e.utf8_error()).in_response(&url))
+    }
+
+    /// Like `fetch_body`, but fails with `Error::ServiceUnavailable` (and retries) if the
+    /// response isn't JSON.
+    fn fetch_json_body(&self, url: Url) -> Result<String, Error> {
+        let (_, _, body) = try!(self.fetch_with(url.clone(), expect_json));
+        String::from_utf8(body).map_err(|e| Error::Utf8(e.utf8_error()).in_response(&url))
+    }
+
+    /// Fetch a response body, retrying transient failures.
+    fn fetch_bytes(&self, url: Url) -> Result<Vec<u8>, Error> {
+        self.fetch(url).map(|(_, _, body)| body)
+    }
+
+    /// Fetch a response's status, headers, and body, retrying transient failures.
+    fn fetch(&self, url: Url) -> Result<(HttpStatus, Headers, Vec<u8>), Error> {
+        self.fetch_with(url, |_, _| Ok(()))
+    }
+
+    /// Like `fetch`, but also retries responses that `check` rejects with a transient error.
+    fn fetch_with<F>(&self, url: Url, check: F) -> Result<(HttpStatus, Headers, Vec<u8>), Error>
+        where F: Fn(&Headers, &[u8]) -> Result<(), Error>,
+    {
+        let mut attempt = 0;
+        loop {
+            if async_aur::is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let start = Instant::now();
+            let result = self.get(url.clone()).and_then(|r| {
+                let (status, headers) = (r.status, r.headers.clone());
+                let body = try!(read_body(r, self.max_response_bytes));
+                try!(check(&headers, &body));
+                Ok((status, headers, body))
+            });
+            match result {
+                Ok((status, _, ref body)) => {
+                    debug!("GET {}: {}, {} bytes in {}ms", url, status, body.len(), millis(start.elapsed()))
+                },
+                Err(ref e) => debug!("GET {}: failed after {}ms: {}", url, millis(start.elapsed()), e),
+            }
+            if result.is_err() && async_aur::is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            match result {
+                Err(Error::RateLimited { retry_after: Some(d) })
+                    if d > Duration::from_secs(MAX_RETRY_AFTER_SECS) => {
+                    return Err(Error::RateLimited { retry_after: Some(d) })
+                },
+                Err(ref e) if attempt < self.max_retries && e.is_transient() => {
+                    let delay = match *e {
+                        Error::RateLimited { retry_after: Some(d) } => d,
+                        _ => Duration::from_millis(RETRY_BACKOFF_MS << cmp::min(attempt, 16)),
+                    };
+                    debug!("Request to {} failed ({}), retrying in {}ms", url, e, millis(delay));
+                    thread::sleep(delay);
+                    attempt += 1;
+                },
+                r => return r,
+            }
+        }
+    }
+
+    fn rpc_raw(&self, url: Url) -> Result<Json, Error> {
+        self.raw_rpc(url).map(|r| r.results)
+    }
+
+    fn rpc(&self, url: Url) -> Result<Vec<Package>, Error> {
+        let body = try!(self.fetch_json_body(url.clone()));
+        let mut pkgs = Vec::new();
+        try!(parse_rpc(body.chars(), |result| {
+            trace!("{:#?}", result);
+            pkgs.push(try!(Package::from_json(result)));
+            Ok(())
+        }).map_err(|e| e.in_response(&url)));
+        Ok(pkgs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util::*;
+
+    #[test]
+    fn multiinfo_chunks_long_requests() {
+        let names: Vec<String> = (0..500).map(|i| format!("synthetic-package-{:03}", i)).collect();
+        let empty = envelope("multiinfo", &[]);
+        let server = TestServer::new((0..20).map(|_| ok(&empty)));
+        let aur = server.aur();
+        aur.multiinfo(names.iter().map(|n| &n[..])).unwrap();
+
+        let targets = server.targets();
+        assert!(targets.len() > 1);
+        assert_eq!(targets.len(), aur.chunk_args("multiinfo", names.iter().map(|n| &n[..])).len());
+        let mut sent = Vec::new();
+        for target in &targets {
+            assert!(server.url(target).len() <= MAX_URL_LEN);
+            let query = form_urlencoded::parse(target.splitn(2, '?').nth(1).unwrap().as_bytes());
+            sent.extend(query.into_iter().filter(|&(ref k, _)| k == "arg[]").map(|(_, v)| v));
+        }
+        assert_eq!(sent, names);
+    }
+
+    #[test]
+    fn requests_carry_user_agent() {
+        let server = TestServer::new(vec![ok(&envelope("search", &[])), ok(&envelope("search", &[]))]);
+        let mut aur = server.aur();
+        aur.search("foo").unwrap();
+        aur.set_user_agent("my-helper/1.0");
+        aur.search("foo").unwrap();
+
+        let requests = server.requests();
+        assert!(requests[0].contains(&format!("User-Agent: {}\r\n", DEFAULT_USER_AGENT)));
+        assert!(requests[1].contains("User-Agent: my-helper/1.0\r\n"));
+    }
+
+    const SEARCH_BYS: &'static [SearchBy] = &[
+        SearchBy::Name,
+        SearchBy::NameDesc,
+        SearchBy::Maintainer,
+        SearchBy::CoMaintainers,
+        SearchBy::Depends,
+        SearchBy::MakeDepends,
+        SearchBy::OptDepends,
+        SearchBy::CheckDepends,
+    ];
+
+    #[test]
+    fn search_by_sends_rpc_field_names() {
+        let expected = ["name", "name-desc", "maintainer", "comaintainers", "depends", "makedepends",
+                        "optdepends", "checkdepends"];
+        let server = TestServer::new(SEARCH_BYS.iter().map(|_| ok(&envelope("search", &[]))));
+        let aur = server.aur();
+        for &by in SEARCH_BYS {
+            aur.search_by("foo", by).unwrap();
+        }
+        let sent: Vec<String> = server.targets()
+            .iter()
+            .map(|t| {
+                form_urlencoded::parse(t.splitn(2, '?').nth(1).unwrap().as_bytes())
+                    .into_iter()
+                    .find(|&(ref k, _)| k == "by")
+                    .unwrap()
+                    .1
+            })
+            .collect();
+        assert_eq!(sent, expected);
+        for (&by, &name) in SEARCH_BYS.iter().zip(&expected) {
+            assert_eq!(by.as_str(), name);
+        }
+    }
+
+    #[test]
+    fn with_base_url_sends_requests_there() {
+        let server = TestServer::new(vec![ok(&envelope("multiinfo", &[package(1, "foo", &[])]))]);
+        let aur = Aur::with_base_url(&server.url("/mirror/rpc/")).unwrap();
+        assert_eq!(aur.info("foo").unwrap().unwrap().name, "foo");
+
+        let targets = server.targets();
+        assert!(targets[0].starts_with("/mirror/rpc/?"));
+        assert_eq!(query_values(&targets[0], "v"), ["5"]);
+        assert!(Aur::with_base_url("not a url").is_err());
+    }
+
+    #[test]
+    fn suggest_parses_bare_array() {
+        let server = TestServer::new(vec![ok(r#"["yay","yay-bin","yay-git"]"#), ok(r#"["yay"]"#)]);
+        let aur = server.aur();
+        assert_eq!(aur.suggest("yay").unwrap(), ["yay", "yay-bin", "yay-git"]);
+        assert_eq!(aur.suggest_pkgbase("yay").unwrap(), ["yay"]);
+
+        let targets = server.targets();
+        assert_eq!(query_values(&targets[0], "type"), ["suggest"]);
+        assert_eq!(query_values(&targets[0], "arg"), ["yay"]);
+        assert_eq!(query_values(&targets[1], "type"), ["suggest-pkgbase"]);
+    }
+
+    #[test]
+    fn license_keeps_order() {
+        let pkg = parse(&package(1, "foo", &[("License", r#"["GPL3","MIT","custom:foo"]"#)]));
+        assert_eq!(pkg.license, ["GPL3", "MIT", "custom:foo"]);
+        assert!(parse(&package(1, "foo", &[("License", "null")])).license.is_empty());
+        assert!(parse(&package(1, "foo", &[])).license.is_empty());
+    }
+
+    #[test]
+    fn parses_dependency_fields() {
+        let pkg = parse(&package(1, "foo", &[
+            ("Depends", r#"["glibc","gtk3>=3.20"]"#),
+            ("MakeDepends", r#"["cargo"]"#),
+            ("CheckDepends", r#"["python-pytest"]"#),
+            ("OptDepends", r#"["git: for git sources"]"#),
+        ]));
+        assert_eq!(pkg.depends, ["glibc", "gtk3>=3.20"]);
+        assert_eq!(pkg.make_depends, ["cargo"]);
+        assert_eq!(pkg.check_depends, ["python-pytest"]);
+        assert_eq!(pkg.opt_depends, ["git: for git sources"]);
+
+        let search = parse(&package(1, "foo", &[]));
+        assert!(search.depends.is_empty() && search.make_depends.is_empty());
+        assert!(search.check_depends.is_empty() && search.opt_depends.is_empty());
+    }
+
+    #[test]
+    fn parses_conflicts_provides_replaces() {
+        let pkg = parse(&package(1, "foo-git", &[
+            ("Conflicts", r#"["foo"]"#),
+            ("Provides", r#"["foo","libfoo.so=1-64"]"#),
+            ("Replaces", "[]"),
+        ]));
+        assert_eq!(pkg.conflicts, ["foo"]);
+        assert_eq!(pkg.provides, ["foo", "libfoo.so=1-64"]);
+        assert!(pkg.replaces.is_empty());
+
+        let absent = parse(&package(1, "foo-git", &[]));
+        assert!(absent.conflicts.is_empty() && absent.provides.is_empty() && absent.replaces.is_empty());
+    }
+
+    #[test]
+    fn parses_keywords() {
+        let pkg = parse(&package(1, "foo", &[("Keywords", r#"["editor","terminal"]"#)]));
+        assert_eq!(pkg.keywords, ["editor", "terminal"]);
+        assert!(parse(&package(1, "foo", &[])).keywords.is_empty());
+        assert!(Package::from_json(Json::from_str(&package(1, "foo", &[("Keywords", "[1]")])).unwrap()).is_err());
+    }
+
+    #[test]
+    fn parses_v5_search_response() {
+        let body = r#"{"resultcount":1,"results":[{"Description":"Yet another yogurt. Pacman wrapper and AUR helper written in go.","FirstSubmitted":1475688004,"ID":1431378,"LastModified":1703753960,"Maintainer":"jguer","Name":"yay","NumVotes":2010,"OutOfDate":null,"PackageBase":"yay","PackageBaseID":115973,"Popularity":37.274387,"URL":"https://github.com/Jguer/yay","URLPath":"/cgit/aur.git/snapshot/yay.tar.gz","Version":"12.2.0-1"}],"type":"search","version":5}"#;
+        let server = TestServer::new(vec![ok(body), ok(r#"{"error":"Incorrect by field specified.","resultcount":0,"results":[],"type":"error","version":5}"#)]);
+        let aur = server.aur();
+
+        let pkgs = aur.search("yay").unwrap();
+        assert_eq!(pkgs.len(), 1);
+        let yay = &pkgs[0];
+        assert_eq!((&yay.name[..], yay.id, &yay.base_name[..], yay.base_id), ("yay", 1431378, "yay", 115973));
+        assert_eq!(yay.version, "12.2.0-1");
+        assert_eq!(yay.maintainer.as_ref().unwrap(), "jguer");
+        assert_eq!(yay.votes, 2010);
+        assert_eq!(yay.out_of_date, None);
+        assert_eq!(yay.created.timestamp(), 1475688004);
+        assert_eq!(yay.modified.timestamp(), 1703753960);
+
+        match aur.search("yay") {
+            Err(Error::Aur(ref msg)) if msg == "Incorrect by field specified." => (),
+            r => panic!("expected the server's error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parses_popularity() {
+        assert_eq!(parse(&package(1, "foo", &[("Popularity", "12.34567")])).popularity, 12.34567);
+        assert_eq!(parse(&package(1, "foo", &[("Popularity", "3")])).popularity, 3.0);
+        assert_eq!(parse(&package(1, "foo", &[("Popularity", "")])).popularity, 0.0);
+    }
+
+    #[test]
+    fn parses_out_of_date() {
+        let flagged = parse(&package(1, "foo", &[("OutOfDate", "1650000000")]));
+        assert_eq!(flagged.out_of_date, Some(NaiveDateTime::from_timestamp(1650000000, 0)));
+        assert!(flagged.is_out_of_date());
+        assert_eq!(parse(&package(1, "foo", &[("OutOfDate", "null")])).out_of_date, None);
+        // Servers before v5 send 0 for packages that aren't flagged.
+        assert_eq!(parse(&package(1, "foo", &[("OutOfDate", "0")])).out_of_date, None);
+    }
+
+    #[test]
+    fn errors_display_and_chain() {
+        use std::error::Error as StdError;
+
+        assert_eq!(Error::Aur("Query arg too small.".to_owned()).to_string(), "AUR error: Query arg too small.");
+        assert_eq!(Error::QueryTooShort.to_string(), "search query must be at least 2 bytes long");
+        let io = Error::Read(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        assert_eq!(io.to_string(), "connection failed: reset");
+        assert_eq!(io.source().unwrap().to_string(), "reset");
+        assert!(Error::NotFound.source().is_none());
+    }
+
+    #[test]
+    fn slow_responses_time_out() {
+        let slow = Reply::Delay(Duration::from_secs(2), Box::new(ok(&envelope("search", &[]))));
+        let server = TestServer::new(vec![slow]);
+        let aur = server.builder().timeout(Some(Duration::from_millis(200))).build().unwrap();
+        let start = Instant::now();
+        match aur.search("foo") {
+            Err(Error::Timeout) => (),
+            r => panic!("expected a timeout, got {:?}", r),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        let server = TestServer::new(vec![Reply::Hangup, Reply::Hangup, ok(&envelope("search", &[]))]);
+        let aur = server.builder().max_retries(2).build().unwrap();
+        aur.search("foo").unwrap();
+        assert_eq!(server.requests().len(), 3);
+
+        let server = TestServer::new(vec![Reply::Hangup, Reply::Hangup]);
+        let aur = server.builder().max_retries(1).build().unwrap();
+        assert!(aur.search("foo").is_err());
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn download_url_resolves_against_base() {
+        let base = Url::parse(AUR_BASE).unwrap();
+        let pkg = parse(&package(1, "foo", &[]));
+        assert_eq!(pkg.download_url(&base).unwrap().serialize(),
+                   "https://aur.archlinux.org/cgit/aur.git/snapshot/foo.tar.gz");
+        let mirror = Url::parse("http://mirror.example/aur/rpc/").unwrap();
+        assert_eq!(pkg.download_url(&mirror).unwrap().serialize(),
+                   "http://mirror.example/cgit/aur.git/snapshot/foo.tar.gz");
+
+        let absolute = parse(&package(1, "foo", &[("URLPath", r#""https://cdn.example/foo.tar.gz""#)]));
+        assert_eq!(absolute.download_url(&base).unwrap().serialize(), "https://cdn.example/foo.tar.gz");
+    }
+
+    #[test]
+    fn git_clone_url_uses_package_base() {
+        let base = Url::parse(AUR_BASE).unwrap();
+        let pkg = parse(&package(1, "python-foo", &[("PackageBase", r#""foo""#)]));
+        assert_eq!(pkg.git_clone_url(&base).unwrap().serialize(), "https://aur.archlinux.org/foo.git");
+    }
+
+    #[test]
+    fn fetches_pkgbuild() {
+        let pkgbuild = "pkgname=foo\npkgver=1.0\n";
+        let server = TestServer::new(vec![ok(pkgbuild), response("404 Not Found", &[], b"")]);
+        let aur = server.aur();
+        assert_eq!(aur.pkgbuild("foo").unwrap(), pkgbuild);
+        match aur.pkgbuild("missing") {
+            Err(Error::NotFound) => (),
+            r => panic!("expected NotFound, got {:?}", r),
+        }
+        assert_eq!(server.targets()[0], "/cgit/aur.git/plain/PKGBUILD?h=foo");
+    }
+
+    #[test]
+    fn parse_rpc_hands_over_each_result_as_it_is_read() {
+        use std::cell::Cell;
+
+        let results: Vec<String> = (0..50).map(|i| package(i, &format!("package-{}", i), &[])).collect();
+        let body = envelope("search", &results);
+        let ends: Vec<usize> = results.iter().map(|r| body.find(&r[..]).unwrap() + r.len()).collect();
+
+        // Count how far into the body the parser has read when each result arrives.
+        let read = Cell::new(0);
+        let chars = body.chars().inspect(|_| read.set(read.get() + 1));
+        let mut parsed = 0;
+        parse_rpc(chars, |result| {
+            // The parser looks at most a character or two past the end of the result.
+            assert!(read.get() <= ends[parsed] + 2, "result {} only arrived after {} chars", parsed, read.get());
+            try!(Package::from_json(result));
+            parsed += 1;
+            Ok(())
+        }).unwrap();
+        assert_eq!(parsed, 50);
+    }
+
+    #[test]
+    fn one_client_serves_many_threads() {
+        let server = TestServer::new((0..8).map(|i| ok(&envelope("multiinfo", &[package(i, "foo", &[])]))));
+        let aur = std::sync::Arc::new(server.aur());
+        let threads: Vec<_> = (0..8).map(|_| {
+            let aur = aur.clone();
+            thread::spawn(move || aur.info("foo").unwrap().unwrap().name)
+        }).collect();
+        for t in threads {
+            assert_eq!(t.join().unwrap(), "foo");
+        }
+        assert_eq!(server.requests().len(), 8);
+    }
+
+    #[test]
+    fn builder_applies_settings() {
+        let server = TestServer::new(vec![Reply::Hangup, ok(&envelope("search", &[]))]);
+        let aur = server.builder()
+            .user_agent("builder-test/2.0")
+            .max_retries(1)
+            .timeout(Some(Duration::from_secs(5)))
+            .build()
+            .unwrap();
+        aur.search("foo").unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains("User-Agent: builder-test/2.0\r\n"));
+
+        match AurBuilder::new().base_url("::").build() {
+            Err(Error::Url(_)) => (),
+            r => panic!("expected a URL error, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn sort_by_keys() {
+        let mut pkgs = vec![
+            parse(&package(1, "beta", &[("NumVotes", "5"), ("Popularity", "0.1"), ("LastModified", "300")])),
+            parse(&package(2, "Alpha", &[("NumVotes", "50"), ("Popularity", "2.5"), ("LastModified", "100")])),
+            parse(&package(3, "gamma", &[("NumVotes", "20"), ("Popularity", "9.0"), ("LastModified", "200")])),
+        ];
+        let ids = |pkgs: &[Package]| pkgs.iter().map(|p| p.id).collect::<Vec<_>>();
+        SortBy::Popularity.sort(&mut pkgs);
+        assert_eq!(ids(&pkgs), [3, 2, 1]);
+        SortBy::Votes.sort(&mut pkgs);
+        assert_eq!(ids(&pkgs), [2, 3, 1]);
+        SortBy::Modified.sort(&mut pkgs);
+        assert_eq!(ids(&pkgs), [1, 3, 2]);
+        SortBy::Name.sort(&mut pkgs);
+        assert_eq!(ids(&pkgs), [2, 1, 3]);
+        let mut sorted = pkgs.clone();
+        sorted.sort();
+        assert_eq!(ids(&sorted), ids(&pkgs));
+    }
+
+    #[test]
+    fn multiinfo_dedups_and_rejects_empty_names() {
+        let server = TestServer::new(vec![ok(&envelope("multiinfo", &[]))]);
+        let aur = server.aur();
+        aur.multiinfo(vec!["foo", "bar", "foo", "baz", "bar"]).unwrap();
+        assert_eq!(query_values(&server.targets()[0], "arg[]"), ["foo", "bar", "baz"]);
+
+        match aur.multiinfo(vec!["foo", ""]) {
+            Err(Error::EmptyArgument) => (),
+            r => panic!("expected EmptyArgument, got {:?}", r),
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn only_name_searches_have_a_minimum_length() {
+        let maintained = envelope("search", &[package(1, "foo", &[("Maintainer", r#""x""#)])]);
+        let server = TestServer::new(vec![ok(&envelope("search", &[])), ok(&maintained), ok(&envelope("search", &[]))]);
+        let aur = server.aur();
+        for &by in &[SearchBy::Name, SearchBy::NameDesc] {
+            match aur.search_by("r", by) {
+                Err(Error::QueryTooShort) => (),
+                r => panic!("expected QueryTooShort, got {:?}", r),
+            }
+        }
+        aur.search_by("r", SearchBy::Depends).unwrap();
+        assert_eq!(aur.packages_by_maintainer("x").unwrap()["foo"].len(), 1);
+        // The AUR counts bytes, so one two-byte character is long enough.
+        aur.search_by("ü", SearchBy::Name).unwrap();
+        match aur.search_by("", SearchBy::Depends) {
+            Err(Error::EmptyArgument) => (),
+            r => panic!("expected EmptyArgument, got {:?}", r),
+        }
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn search_raw_returns_unparseable_results() {
+        let broken = package(1, "foo", &[("Name", "")]);
+        let server = TestServer::new(vec![ok(&envelope("search", &[broken.clone()])), ok(&envelope("search", &[broken.clone()]))]);
+        let aur = server.aur();
+        assert!(aur.search("foo").is_err());
+        let raw = aur.search_raw("foo").unwrap();
+        assert_eq!(raw, Json::Array(vec![Json::from_str(&broken).unwrap()]));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trips_packages() {
+        let info = parse(&package(1, "foo", &[
+            ("OutOfDate", "1650000000"),
+            ("License", r#"["MIT"]"#),
+            ("Depends", r#"["bar>=2"]"#),
+            ("Keywords", r#"["baz"]"#),
+        ]));
+        let json = serde_json::to_string(&info).unwrap();
+        let back: Package = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.out_of_date, info.out_of_date);
+        assert_eq!((back.license, back.depends, back.keywords), (info.license, info.depends, info.keywords));
+        assert_eq!(back.created, info.created);
+
+        // Search results lack the info-only fields, and unflagged packages may lack OutOfDate.
+        let search = package(2, "bar", &[("OutOfDate", "")]);
+        let pkg: Package = serde_json::from_str(&search).unwrap();
+        assert_eq!((&pkg.name[..], pkg.out_of_date), ("bar", None));
+        assert!(pkg.depends.is_empty() && pkg.license.is_empty() && pkg.co_maintainers.is_empty());
+
+        // Like from_json, take missing vote counts and popularity as zero.
+        let unvoted = package(3, "baz", &[("NumVotes", ""), ("Popularity", "")]);
+        let pkg: Package = serde_json::from_str(&unvoted).unwrap();
+        assert_eq!((pkg.votes, pkg.popularity), (0, 0.0));
+        assert_eq!((pkg.votes, pkg.popularity), (parse(&unvoted).votes, parse(&unvoted).popularity));
+    }
+
+    #[test]
+    fn too_many_results_is_typed() {
+        let body = r#"{"version":5,"type":"error","resultcount":0,"results":[],"error":"Too many package results."}"#;
+        let server = TestServer::new(vec![ok(body)]);
+        match server.aur().search("li") {
+            Err(Error::TooManyResults) => (),
+            r => panic!("expected TooManyResults, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parses_co_maintainers() {
+        let pkg = parse(&package(1, "foo", &[("CoMaintainers", r#"["bob","carol"]"#)]));
+        assert_eq!(pkg.co_maintainers, ["bob", "carol"]);
+        assert!(parse(&package(1, "foo", &[])).co_maintainers.is_empty());
+    }
+
+    #[test]
+    fn with_client_uses_the_given_client() {
+        let server = TestServer::new(vec![ok(&envelope("search", &[package(1, "foo", &[])]))]);
+        let aur = Aur::with_client(Client::new(), Url::parse(&server.url("/rpc/")).unwrap());
+        assert_eq!(aur.search("foo").unwrap()[0].name, "foo");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn orphans_have_no_maintainer() {
+        assert!(parse(&package(1, "foo", &[("Maintainer", "null")])).is_orphan());
+        assert!(parse(&package(1, "foo", &[("Maintainer", "")])).is_orphan());
+        assert!(!parse(&package(1, "foo", &[])).is_orphan());
+    }
+
+    #[test]
+    fn info_by_base_looks_up_split_packages() {
+        let base = |id, name: &str, base: &str| package(id, name, &[("PackageBase", &format!("{:?}", base))]);
+        let srcinfo = |base: &str, names: &[&str]| {
+            let names: Vec<String> = names.iter().map(|n| format!("pkgname = {}\n", n)).collect();
+            ok(&format!("pkgbase = {}\n\tpkgver = 1.0\n\tpkgrel = 1\n\n{}", base, names.concat()))
+        };
+        let server = TestServer::new(vec![
+            // A one-letter base whose packages don't all contain its name.
+            srcinfo("r", &["r", "libr-devel"]),
+            ok(&envelope("multiinfo", &[base(1, "r", "r"), base(2, "libr-devel", "r")])),
+            // A base too common to search for.
+            srcinfo("python", &["python"]),
+            ok(&envelope("multiinfo", &[base(3, "python", "python")])),
+            response("404 Not Found", &[], b""),
+        ]);
+        let aur = server.aur();
+        assert_eq!(aur.info_by_base("r").unwrap().iter().map(|p| p.id).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(aur.info_by_base("python").unwrap()[0].id, 3);
+        assert!(aur.info_by_base("missing").unwrap().is_empty());
+
+        let targets = server.targets();
+        assert_eq!(targets[0], "/cgit/aur.git/plain/.SRCINFO?h=r");
+        assert_eq!(query_values(&targets[1], "arg[]"), ["r", "libr-devel"]);
+        assert_eq!(targets[4], "/cgit/aur.git/plain/.SRCINFO?h=missing");
+        assert!(targets.iter().all(|t| query_values(t, "type") != ["search"]));
+    }
+
+    #[test]
+    fn decompresses_gzip_and_deflate() {
+        use flate2::Compression;
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use std::io::Write;
+
+        let body = envelope("search", &[package(1, "foo", &[])]);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::Default);
+        gzip.write_all(body.as_bytes()).unwrap();
+        let mut deflate = ZlibEncoder::new(Vec::new(), Compression::Default);
+        deflate.write_all(body.as_bytes()).unwrap();
+        let server = TestServer::new(vec![
+            response("200 OK", &[("Content-Encoding", "gzip")], &gzip.finish().unwrap()),
+            response("200 OK", &[("Content-Encoding", "deflate")], &deflate.finish().unwrap()),
+        ]);
+        let aur = server.aur();
+        assert_eq!(aur.search("foo").unwrap()[0].name, "foo");
+        assert_eq!(aur.search("foo").unwrap()[0].name, "foo");
+        assert!(server.requests()[0].contains("Accept-Encoding: gzip, deflate\r\n"));
+    }
+
+    #[test]
+    fn data_after_a_compressed_body_is_limited() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::Default);
+        gzip.write_all(envelope("search", &[package(1, "foo", &[])]).as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let trailing = |len| {
+            let mut body = gzip.clone();
+            body.extend(iter::repeat(b'x').take(len));
+            response("200 OK", &[("Content-Encoding", "gzip")], &body)
+        };
+        let server = TestServer::new(vec![trailing(100), trailing(100000)]);
+        let aur = server.builder().max_response_bytes(10000).build().unwrap();
+        assert_eq!(aur.search("foo").unwrap()[0].name, "foo");
+        match aur.search("foo") {
+            Err(Error::ResponseTooLarge) => (),
+            r => panic!("expected ResponseTooLarge, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn search_filtered_hides_out_of_date() {
+        let found = envelope("search", &[package(1, "foo", &[]), package(2, "foo-git", &[("OutOfDate", "1650000000")])]);
+        let server = TestServer::new(vec![ok(&found), ok(&found)]);
+        let aur = server.aur();
+        let ids = |pkgs: Vec<Package>| pkgs.iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids(aur.search_filtered("foo", false).unwrap()), [1]);
+        assert_eq!(ids(aur.search_filtered("foo", true).unwrap()), [1, 2]);
+    }
+
+    #[test]
+    fn parses_numbers_sent_as_strings() {
+        let pkg = parse(&package(1, "foo", &[("NumVotes", r#""42""#), ("ID", r#""7""#), ("PackageBaseID", r#""8""#)]));
+        assert_eq!((pkg.votes, pkg.id, pkg.base_id), (42, 7, 8));
+        assert_eq!(parse(&package(1, "foo", &[("NumVotes", "")])).votes, 0);
+        assert!(Package::from_json(Json::from_str(&package(1, "foo", &[("NumVotes", r#""many""#)])).unwrap()).is_err());
+    }
+
+    #[test]
+    fn exists_reports_each_name() {
+        let server = TestServer::new(vec![ok(&envelope("multiinfo", &[package(1, "foo", &[])]))]);
+        let found = server.aur().exists(vec!["foo", "Foo", "missing"]).unwrap();
+        assert_eq!(found.len(), 3);
+        assert!(found["foo"] && found["Foo"] && !found["missing"]);
+    }
+
+    #[test]
+    fn redirect_policy_limits_redirects() {
+        let moved = |to: &str| response("302 Found", &[("Location", to)], b"");
+        let server = TestServer::new(vec![
+            moved("/a/"),
+            moved("/a/"), moved("/b/"), ok(&envelope("search", &[])),
+            moved("/a/"), moved("/b/"),
+        ]);
+        let is_redirect = |r: Result<Vec<Package>, Error>| match r {
+            Err(Error::Http { code: HttpStatus::Found, .. }) => true,
+            _ => false,
+        };
+
+        let aur = server.builder().follow_redirects(false).build().unwrap();
+        assert!(is_redirect(aur.search("foo")));
+        let aur = server.builder().redirect_policy(RedirectPolicy::FollowAll).build().unwrap();
+        aur.search("foo").unwrap();
+        let aur = server.builder().redirect_policy(RedirectPolicy::FollowN(1)).build().unwrap();
+        assert!(is_redirect(aur.search("foo")));
+
+        let targets = server.targets();
+        assert_eq!(targets.len(), 6);
+        assert_eq!(&targets[2..4], ["/a/", "/b/"]);
+    }
+
+    #[test]
+    fn search_limited_keeps_most_popular() {
+        let found = envelope("search", &[
+            package(1, "a", &[("Popularity", "1.0")]),
+            package(2, "b", &[("Popularity", "5.0")]),
+            package(3, "c", &[("Popularity", "3.0")]),
+        ]);
+        let server = TestServer::new(vec![ok(&found), ok(&found)]);
+        let aur = server.aur();
+        assert_eq!(aur.search_limited("foo", 2).unwrap().iter().map(|p| p.id).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(aur.search_limited("foo", 10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn fetched_at_is_parse_time() {
+        let before = now();
+        let pkg = parse(&package(1, "foo", &[]));
+        assert!(pkg.fetched_at >= before && pkg.fetched_at <= now());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn fetched_at_is_not_serialized() {
+        let pkg = parse(&package(1, "foo", &[]));
+        let json = serde_json::to_string(&pkg).unwrap();
+        assert!(!json.contains("FetchedAt"));
+        let before = now();
+        let back: Package = serde_json::from_str(&json).unwrap();
+        assert!(back.fetched_at >= before && back.fetched_at <= now());
+    }
+
+    #[test]
+    fn multiinfo_parallel_overlaps_requests() {
+        let names: Vec<String> = (0..500).map(|i| format!("synthetic-package-{:03}", i)).collect();
+        let delay = Duration::from_millis(300);
+        let server = TestServer::new((0..20).map(|i| {
+            Reply::Delay(delay, Box::new(ok(&envelope("multiinfo", &[package(i, "foo", &[])]))))
+        }));
+        let aur = server.aur();
+        let chunks = aur.chunk_args("multiinfo", names.iter().map(|n| &n[..])).len();
+        assert!(chunks >= 4);
+
+        let start = Instant::now();
+        let pkgs = aur.multiinfo_parallel(names.iter().map(|n| &n[..]), chunks).unwrap();
+        assert!(start.elapsed() < delay * 2, "took {:?}", start.elapsed());
+        assert_eq!(pkgs.len(), chunks);
+        assert_eq!(server.requests().len(), chunks);
+        assert_eq!(server.connections(), chunks);
+    }
+
+    #[test]
+    fn info_requests_use_arg_array() {
+        let server = TestServer::new(vec![
+            ok(&envelope("multiinfo", &[package(1, "foo", &[])])),
+            ok(&envelope("multiinfo", &[])),
+            ok(&envelope("multiinfo", &[package(1, "foo", &[]), package(2, "foo", &[])])),
+            ok(&envelope("multiinfo", &[package(1, "foo", &[])])),
+        ]);
+        let aur = server.aur();
+        assert_eq!(aur.info("foo").unwrap().unwrap().id, 1);
+        assert!(aur.info("missing").unwrap().is_none());
+        assert_eq!(aur.info_all("foo").unwrap().len(), 2);
+        assert_eq!(aur.info_raw("foo").unwrap().as_array().unwrap().len(), 1);
+        for target in server.targets() {
+            assert_eq!(query_values(&target, "type"), ["info"]);
+            assert!(query_values(&target, "arg").is_empty());
+            assert_eq!(query_values(&target, "arg[]").len(), 1);
+        }
+    }
+
+    #[test]
+    fn packages_are_identified_by_id() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |p: &Package| {
+            let mut h = DefaultHasher::new();
+            p.hash(&mut h);
+            h.finish()
+        };
+        let old = parse(&package(1, "foo", &[("Version", r#""1.0-1""#)]));
+        let new = parse(&package(1, "foo", &[("Version", r#""2.0-1""#)]));
+        assert_eq!(old, new);
+        assert_eq!(hash(&old), hash(&new));
+        assert!(old != parse(&package(2, "foo", &[])));
+        let set: HashSet<Package> = vec![old, new].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn packages_sort_by_name_ignoring_case() {
+        let mut pkgs = vec![
+            parse(&package(3, "zsh", &[])),
+            parse(&package(2, "Bash", &[])),
+            parse(&package(4, "bash", &[])),
+            parse(&package(1, "ash", &[])),
+        ];
+        pkgs.sort();
+        assert_eq!(pkgs.iter().map(|p| p.id).collect::<Vec<_>>(), [1, 2, 4, 3]);
+        assert!(pkgs[1] < pkgs[2]);
+    }
+
+    #[test]
+    fn download_fetches_snapshot() {
+        let tarball = b"\x1f\x8b\x08\x00not really a tarball";
+        let server = TestServer::new(vec![
+            response("200 OK", &[("Content-Type", "application/x-gzip")], tarball),
+            response("404 Not Found", &[], b"Not found"),
+        ]);
+        let aur = server.aur();
+        let pkg = parse(&package(1, "foo", &[]));
+        assert_eq!(aur.download(&pkg).unwrap(), &tarball[..]);
+        match aur.download(&pkg) {
+            Err(Error::NotFound) => (),
+            r => panic!("expected NotFound, got {:?}", r),
+        }
+        assert_eq!(server.targets()[0], "/cgit/aur.git/snapshot/foo.tar.gz");
+    }
+
+    #[test]
+    fn parse_errors_carry_the_url() {
+        let server = TestServer::new(vec![response("200 OK", &[], b"{\"results\": [\"\xff\"]}"), ok("{\"results\": [")]);
+        let aur = server.aur();
+        for expected in &["invalid UTF-8", "failed to parse"] {
+            match aur.search("foo") {
+                Err(e @ Error::Context { .. }) => {
+                    let msg = e.to_string();
+                    assert!(msg.starts_with(expected) && msg.contains(&server.url("/rpc/?v=5")), "{}", msg);
+                },
+                r => panic!("expected an error with context, got {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn packages_by_maintainer_groups_by_base() {
+        let base = |id, name: &str, base: &str| package(id, name, &[("PackageBase", &format!("{:?}", base))]);
+        let found = envelope("search", &[base(1, "foo", "foo"), base(2, "foo-docs", "foo"), base(3, "bar", "bar")]);
+        let server = TestServer::new(vec![ok(&found)]);
+        let bases = server.aur().packages_by_maintainer("alice").unwrap();
+        assert_eq!(bases.len(), 2);
+        assert_eq!(bases["foo"].iter().map(|p| p.id).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(bases["bar"][0].id, 3);
+        assert_eq!(query_values(&server.targets()[0], "by"), ["maintainer"]);
+    }
+
+    #[test]
+    fn oversized_responses_are_rejected() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let big = vec![b' '; 4096];
+        let mut bomb = GzEncoder::new(Vec::new(), Compression::Best);
+        bomb.write_all(&big).unwrap();
+        let server = TestServer::new(vec![
+            ok(&envelope("search", &[])),
+            response("200 OK", &[], &big),
+            response("200 OK", &[("Content-Encoding", "gzip")], &bomb.finish().unwrap()),
+        ]);
+        let aur = server.builder().max_response_bytes(1024).build().unwrap();
+        aur.search("foo").unwrap();
+        for _ in 0..2 {
+            match aur.search("foo") {
+                Err(Error::ResponseTooLarge) => (),
+                r => panic!("expected ResponseTooLarge, got {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn network_errors_say_where_they_failed() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let refused = Aur::with_base_url(&format!("http://127.0.0.1:{}/rpc/", port)).unwrap();
+        match refused.search("foo") {
+            Err(Error::Connect(_)) => (),
+            r => panic!("expected a connect error, got {:?}", r),
+        }
+
+        let server = TestServer::new(vec![Reply::Hangup]);
+        match server.aur().search("foo") {
+            Err(Error::Read(_)) => (),
+            r => panic!("expected a read error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn search_iter_parses_on_demand() {
+        let mut results: Vec<String> = (0..3).map(|i| package(i, &format!("foo-{}", i), &[])).collect();
+        results.push(package(3, "broken", &[("Name", "")]));
+        let server = TestServer::new(vec![ok(&envelope("search", &results)), ok(&envelope("search", &results))]);
+        let aur = server.aur();
+
+        let first: Vec<_> = aur.search_iter("foo").unwrap().take(2).map(|p| p.unwrap().id).collect();
+        assert_eq!(first, [0, 1]);
+        // The iterator read the whole response, so the connection is free for the next request.
+        let rest: Vec<_> = aur.search_iter("foo").unwrap().skip(2).collect();
+        assert_eq!(rest[0].as_ref().unwrap().id, 2);
+        assert!(rest[1].is_err());
+        assert_eq!(rest.len(), 2);
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[test]
+    fn raw_rpc_exposes_status_and_headers() {
+        let body = envelope("search", &[package(1, "foo", &[])]);
+        let server = TestServer::new(vec![response("200 OK", &[("X-Cache", "HIT")], body.as_bytes())]);
+        let aur = server.aur();
+        let url = Url::parse(&server.url("/rpc/?v=5&type=search&arg=foo")).unwrap();
+        let response = aur.raw_rpc(url).unwrap();
+        assert_eq!(response.status, HttpStatus::Ok);
+        assert_eq!(response.headers.get_raw("X-Cache").unwrap()[0], b"HIT");
+        assert_eq!((response.version, response.result_count), (Some(5), Some(1)));
+        assert_eq!(response.results.as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn parses_groups() {
+        let pkg = parse(&package(1, "foo", &[("Groups", r#"["xfce4-goodies"]"#)]));
+        assert_eq!(pkg.groups, ["xfce4-goodies"]);
+        assert!(parse(&package(1, "foo", &[])).groups.is_empty());
+    }
+
+    #[test]
+    fn out_of_range_timestamps_are_errors() {
+        let pkg = |secs: &str| Package::from_json(Json::from_str(&package(1, "foo", &[("LastModified", secs)])).unwrap());
+        assert_eq!(pkg("1600000000").unwrap().modified.timestamp(), 1600000000);
+        for &bad in &["-1", "18446744073709551615", "100000000000000000", "1.5"] {
+            match pkg(bad) {
+                Err(Error::InvalidTimestamp { field: "LastModified", ref value }) => assert_eq!(value, bad),
+                r => panic!("expected an invalid timestamp for {}, got {:?}", bad, r),
+            }
+        }
+        assert!(pkg(r#""yesterday""#).is_err());
+    }
+
+    #[test]
+    fn parses_submitter() {
+        let pkg = parse(&package(1, "foo", &[("Submitter", r#""bob""#)]));
+        assert_eq!(pkg.submitter.as_ref().unwrap(), "bob");
+        assert_eq!(parse(&package(1, "foo", &[("Submitter", "null")])).submitter, None);
+        assert_eq!(parse(&package(1, "foo", &[])).submitter, None);
+    }
+
+    #[test]
+    fn redirects_are_limited_to_allowed_hosts() {
+        let mut aur = Aur::new();
+        aur.set_allowed_redirect_hosts(vec!["*.ArchLinux.org".to_owned(), "Mirror.example".to_owned()]);
+        assert!(aur.redirect_allowed("aur.archlinux.org"));
+        assert!(aur.redirect_allowed("AUR.ARCHLINUX.ORG"));
+        assert!(aur.redirect_allowed("mirror.EXAMPLE"));
+        assert!(!aur.redirect_allowed("archlinux.org.evil.example"));
+        assert!(!aur.redirect_allowed("evilarchlinux.org"));
+
+        let server = TestServer::new(vec![response("302 Found", &[("Location", "http://evil.example/rpc/")], b"")]);
+        let aur = server.builder().allowed_redirect_hosts(vec!["127.0.0.1".to_owned()]).build().unwrap();
+        match aur.search("foo") {
+            Err(Error::DisallowedRedirect { ref host }) if host == "evil.example" => (),
+            r => panic!("expected a disallowed redirect, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn resolve_deps_walks_the_tree() {
+        let server = TestServer::new(vec![
+            ok(&envelope("multiinfo", &[package(1, "app", &[("Depends", r#"["libfoo>=2","glibc"]"#), ("MakeDepends", r#"["tool"]"#)])])),
+            ok(&envelope("multiinfo", &[package(2, "libfoo", &[("Depends", r#"["app"]"#)]), package(3, "tool", &[])])),
+        ]);
+        let pkgs = server.aur().resolve_deps("app").unwrap();
+        assert_eq!(pkgs.iter().map(|p| &p.name[..]).collect::<Vec<_>>(), ["app", "libfoo", "tool"]);
+
+        let targets = server.targets();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(query_values(&targets[1], "arg[]"), ["libfoo", "glibc", "tool"]);
+    }
+
+    #[test]
+    fn displays_like_pacman() {
+        let pkg = parse(&package(1, "foo", &[("Version", r#""1.2-3""#), ("NumVotes", "42")]));
+        assert_eq!(pkg.to_string(), "aur/foo 1.2-3 (42)");
+        let flagged = parse(&package(1, "foo", &[("OutOfDate", "1650000000")]));
+        assert_eq!(flagged.to_string(), "aur/foo 1.0-1 (10) [out-of-date]");
+    }
+
+    #[test]
+    fn call_raw_sends_custom_parameters() {
+        let server = TestServer::new(vec![ok(&envelope("search", &[package(1, "foo", &[])]))]);
+        let results = server.aur().call_raw(&[("type", "search"), ("by", "keywords"), ("arg", "foo")]).unwrap();
+        assert_eq!(results.as_array().unwrap().len(), 1);
+        assert_eq!(query(&server.targets()[0]), [
+            ("v".to_owned(), "5".to_owned()),
+            ("type".to_owned(), "search".to_owned()),
+            ("by".to_owned(), "keywords".to_owned()),
+            ("arg".to_owned(), "foo".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn arguments_are_percent_encoded() {
+        let names = ["gtk+", "foo bar", "c#", "ü", "a&b=c"];
+        let server = TestServer::new((0..names.len() * 2 + 1).map(|_| ok(&envelope("multiinfo", &[]))));
+        let aur = server.aur();
+        for name in &names {
+            aur.info(name).unwrap();
+            aur.search(name).unwrap();
+        }
+        aur.multiinfo(vec!["gtk+", "c#"]).unwrap();
+
+        let targets = server.targets();
+        assert!(targets[0].ends_with("&arg%5B%5D=gtk%2B"), "{}", targets[0]);
+        assert!(targets[1].ends_with("&arg=gtk%2B"), "{}", targets[1]);
+        assert!(targets[3].ends_with("&arg=foo+bar"), "{}", targets[3]);
+        assert!(targets[5].ends_with("&arg=c%23"), "{}", targets[5]);
+        assert!(targets[7].ends_with("&arg=%C3%BC"), "{}", targets[7]);
+        assert!(targets[9].ends_with("&arg=a%26b%3Dc"), "{}", targets[9]);
+        for (pair, name) in targets.chunks(2).zip(&names) {
+            assert_eq!(query_values(&pair[0], "arg[]"), [*name]);
+            assert_eq!(query_values(&pair[1], "arg"), [*name]);
+        }
+        assert_eq!(query_values(&targets[10], "arg[]"), ["gtk+", "c#"]);
+    }
+
+    #[test]
+    fn needs_upgrade_compares_versions() {
+        let pkg = parse(&package(1, "foo", &[("Version", r#""1:2.0-1""#)]));
+        assert!(pkg.needs_upgrade("1:1.9-3").unwrap());
+        assert!(pkg.needs_upgrade("2.5-1").unwrap());
+        assert!(!pkg.needs_upgrade("1:2.0-1").unwrap());
+        assert!(!pkg.needs_upgrade("2:0.1-1").unwrap());
+        match pkg.needs_upgrade("") {
+            Err(Error::InvalidVersion(_)) => (),
+            r => panic!("expected InvalidVersion, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn check_upgrades_reports_newer_and_missing() {
+        let found = envelope("multiinfo", &[
+            package(1, "foo", &[("Version", r#""2.0-1""#)]),
+            package(2, "bar", &[("Version", r#""1.0-1""#)]),
+        ]);
+        let server = TestServer::new(vec![ok(&found)]);
+        let installed: Vec<(String, String)> = vec![("foo", "1.0-1"), ("bar", "1.0-1"), ("gone", "3")]
+            .into_iter()
+            .map(|(n, v)| (n.to_owned(), v.to_owned()))
+            .collect();
+        let (upgrades, missing) = server.aur().check_upgrades(&installed).unwrap();
+        assert_eq!(upgrades.iter().map(|&(ref p, newer)| (&p.name[..], newer)).collect::<Vec<_>>(),
+                   [("foo", true), ("bar", false)]);
+        assert_eq!(missing, ["gone"]);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn idle_connections_are_reused() {
+        let empty = envelope("search", &[]);
+        let server = TestServer::new(vec![ok(&empty), ok(&empty)]);
+        let aur = server.aur();
+        aur.search("foo").unwrap();
+        aur.search("foo").unwrap();
+        assert_eq!(server.connections(), 1);
+
+        let server = TestServer::new(vec![ok(&empty), ok(&empty)]);
+        let aur = server.builder().max_idle_connections(0).build().unwrap();
+        aur.search("foo").unwrap();
+        aur.search("foo").unwrap();
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[test]
+    fn search_count_counts_results() {
+        let results: Vec<String> = (0..3).map(|i| package(i, "foo", &[("Name", "")])).collect();
+        let server = TestServer::new(vec![ok(&envelope("search", &results))]);
+        // The results aren't parsed, so ones that wouldn't parse are still counted.
+        assert_eq!(server.aur().search_count("foo").unwrap(), 3);
+    }
+
+    #[test]
+    fn checks_envelope_version() {
+        let v4 = r#"{"version":4,"type":"search","resultcount":0,"results":[]}"#;
+        let unversioned = r#"{"type":"search","resultcount":0,"results":[]}"#;
+        let server = TestServer::new(vec![ok(v4), ok(unversioned)]);
+        let aur = server.aur();
+        match aur.search("foo") {
+            Err(Error::UnsupportedProtocol { got: 4 }) => (),
+            r => panic!("expected UnsupportedProtocol, got {:?}", r),
+        }
+        assert!(aur.search("foo").unwrap().is_empty());
+    }
+
+    #[test]
+    fn default_search_by_applies_to_search() {
+        let empty = envelope("search", &[]);
+        let server = TestServer::new(vec![ok(&empty), ok(&empty), ok(&empty)]);
+        let mut aur = server.builder().default_search_by(SearchBy::Name).build().unwrap();
+        aur.search("foo").unwrap();
+        aur.set_default_search_by(SearchBy::Depends);
+        aur.search("foo").unwrap();
+        aur.search_count("x").unwrap();
+        let by: Vec<_> = server.targets().iter().map(|t| query_values(t, "by")).collect();
+        assert_eq!(by, [["name"], ["depends"], ["depends"]]);
+    }
+
+    #[test]
+    fn all_package_names_reads_the_dump() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let list = "# AUR package list, generated on Fri, 01 Jan 2021\nfoo\nbar\n\nbaz\n";
+        let mut gz = GzEncoder::new(Vec::new(), Compression::Default);
+        gz.write_all(list.as_bytes()).unwrap();
+        let gz = gz.finish().unwrap();
+        let server = TestServer::new(vec![
+            response("200 OK", &[("Content-Type", "application/gzip")], &gz),
+            response("200 OK", &[("Content-Encoding", "gzip")], &gz),
+        ]);
+        let aur = server.aur();
+        assert_eq!(aur.all_package_names().unwrap(), ["foo", "bar", "baz"]);
+        assert_eq!(aur.all_package_names().unwrap(), ["foo", "bar", "baz"]);
+        assert_eq!(server.targets()[0], "/packages.gz");
+    }
+
+    #[test]
+    fn requests_are_logged_with_timing() {
+        logs();
+        let server = TestServer::new(vec![ok(&envelope("search", &[])), Reply::Hangup]);
+        let aur = server.aur();
+        aur.search("foo").unwrap();
+        aur.search("foo").unwrap_err();
+
+        let prefix = format!("GET {}", server.url("/rpc/?v=5&type=search"));
+        let ours: Vec<String> = logs().into_iter().filter(|l| l.starts_with(&prefix)).collect();
+        assert_eq!(ours.len(), 2);
+        assert!(ours[0].contains(": 200 OK, ") && ours[0].ends_with("ms"), "{}", ours[0]);
+        assert!(ours[1].contains(": failed after ") && ours[1].contains("ms: "), "{}", ours[1]);
+    }
+
+    #[test]
+    fn multiinfo_lenient_collects_bad_packages() {
+        let results = [package(1, "foo", &[]), package(2, "bar", &[("Version", "")]), package(3, "baz", &[])];
+        let server = TestServer::new(vec![ok(&envelope("multiinfo", &results)), ok("{\"results\": [")]);
+        let aur = server.aur();
+        let (pkgs, errors) = aur.multiinfo_lenient(vec!["foo", "bar", "baz"]).unwrap();
+        assert_eq!(pkgs.iter().map(|p| p.id).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(errors.len(), 1);
+        assert!(aur.multiinfo_lenient(vec!["foo"]).is_err());
+    }
+
+    #[test]
+    fn http2_errors_are_typed() {
+        // The server must open with a SETTINGS frame, not a PING.
+        let server = TestServer::new(vec![Reply::Raw(b"\0\0\x08\x06\0\0\0\0\0pingping".to_vec())]);
+        let client = Client::with_protocol(hyper::http::h2::new_protocol());
+        let aur = Aur::with_client(client, Url::parse(&server.url("/rpc/")).unwrap());
+        match aur.search("foo") {
+            Err(e @ Error::Http2(_)) => assert!(e.to_string().starts_with("HTTP/2 error: ")),
+            r => panic!("expected an HTTP/2 error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn malformed_responses_are_transport_errors() {
+        let server = TestServer::new(vec![Reply::Raw(b"HTTP/1.1 abc Broken\r\n\r\n".to_vec())]);
+        match server.aur().search("foo") {
+            Err(Error::Transport(_)) => (),
+            r => panic!("expected a transport error, got {:?}", r),
+        }
+        let url = UrlError::InvalidPort;
+        match Error::from(hyper::Error::Uri(url)) {
+            Error::Url(UrlError::InvalidPort) => (),
+            e => panic!("expected a URL error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn aur_web_url_points_at_package_page() {
+        assert_eq!(parse(&package(1, "python-foo", &[])).aur_web_url(), "https://aur.archlinux.org/packages/python-foo");
+    }
+
+    #[test]
+    fn configured_ssl_secures_https_connections() {
+        use hyper::net::HttpStream;
+
+        /// Records the hosts it's asked to secure, and leaves their streams as they are.
+        #[derive(Clone, Debug)]
+        struct RecordingSsl(Arc<Mutex<Vec<String>>>);
+
+        impl Ssl for RecordingSsl {
+            type Stream = HttpStream;
+
+            fn wrap_client(&self, stream: HttpStream, host: &str) -> hyper::Result<HttpStream> {
+                self.0.lock().unwrap().push(host.to_owned());
+                Ok(stream)
+            }
+
+            fn wrap_server(&self, stream: HttpStream) -> hyper::Result<HttpStream> {
+                Ok(stream)
+            }
+        }
+
+        let found = envelope("search", &[package(1, "foo", &[])]);
+        let server = TestServer::new(vec![
+            ok(&found),
+            Reply::Raw(b"HTTP/1.1 200 Connection established\r\n\r\n".to_vec()),
+            ok(&found),
+        ]);
+        let hosts = Arc::new(Mutex::new(Vec::new()));
+        let direct = AurBuilder::new()
+            .base_url(&format!("https://127.0.0.1:{}/rpc/", server.port()))
+            .ssl(RecordingSsl(hosts.clone()))
+            .build()
+            .unwrap();
+        assert_eq!(direct.search("foo").unwrap()[0].name, "foo");
+        let proxied = AurBuilder::new()
+            .base_url("https://aur.example/rpc/")
+            .proxy("127.0.0.1", server.port())
+            .ssl(RecordingSsl(hosts.clone()))
+            .build()
+            .unwrap();
+        assert_eq!(proxied.search("foo").unwrap()[0].name, "foo");
+
+        assert_eq!(*hosts.lock().unwrap(), ["127.0.0.1", "aur.example"]);
+        assert!(server.requests()[1].starts_with("CONNECT aur.example:443 "));
+    }
+
+    #[test]
+    fn rate_limits_are_retried_after_the_requested_delay() {
+        let server = TestServer::new(vec![
+            response("429 Too Many Requests", &[("Retry-After", "1")], b""),
+            ok(&envelope("search", &[package(1, "foo", &[])])),
+        ]);
+        let aur = server.builder().max_retries(1).build().unwrap();
+        let start = Instant::now();
+        assert_eq!(aur.search("foo").unwrap()[0].name, "foo");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn long_retry_after_fails_without_sleeping() {
+        let server = TestServer::new(vec![
+            response("429 Too Many Requests", &[("Retry-After", "3600")], b""),
+        ]);
+        let aur = server.builder().max_retries(3).build().unwrap();
+        let start = Instant::now();
+        match aur.search("foo") {
+            Err(Error::RateLimited { retry_after: Some(d) }) => assert_eq!(d, Duration::from_secs(3600)),
+            r => panic!("expected a rate limit, got {:?}", r),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn category_ids_map_to_names() {
+        let pkg = parse(&package(1, "foo", &[("CategoryID", "6")]));
+        assert_eq!(pkg.category(), Some(Category::Games));
+        assert_eq!(Category::from_id(3).map(|c| c.as_str()), Some("devel"));
+        assert_eq!(Category::from_id(20), Some(Category::Fonts));
+        assert_eq!(Category::from_id(1), None);
+        assert_eq!(Category::from_id(99), None);
+        assert_eq!(parse(&package(1, "foo", &[])).category(), None);
+    }
+
+    #[test]
+    fn queries_do_not_bleed_between_calls() {
+        let server = TestServer::new(vec![
+            ok(&envelope("search", &[package(1, "foo", &[])])),
+            ok(&envelope("multiinfo", &[])),
+        ]);
+        let aur = Aur::with_client(hyper::Client::new(), Url::parse(&server.url("/rpc/?key=abc")).unwrap());
+        aur.search("foo").unwrap();
+        aur.info("bar").unwrap();
+        let targets = server.targets();
+        let first = query(&targets[0]);
+        let second = query(&targets[1]);
+        let pair = |k: &str, v: &str| (k.to_owned(), v.to_owned());
+        assert_eq!(first, vec![pair("v", "5"), pair("key", "abc"), pair("type", "search"), pair("by", "name-desc"), pair("arg", "foo")]);
+        assert_eq!(second, vec![pair("v", "5"), pair("key", "abc"), pair("type", "info"), pair("arg[]", "bar")]);
+    }
+
+    #[test]
+    fn short_results_are_incomplete() {
+        let body = r#"{"version":5,"type":"search","resultcount":3,"results":[PKG]}"#
+            .replace("PKG", &package(1, "foo", &[]));
+        let server = TestServer::new(vec![ok(&body)]);
+        match server.aur().search("foo") {
+            Err(Error::IncompleteResponse { expected: 3, got: 1 }) => (),
+            r => panic!("expected an incomplete response, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn snapshot_urls_pin_a_ref() {
+        let pkg = parse(&package(1, "foo", &[("PackageBase", r#""foo-base""#)]));
+        let base = Url::parse("https://aur.archlinux.org/").unwrap();
+        assert_eq!(pkg.snapshot_url_for(&base, "abc123").unwrap().serialize(),
+                   "https://aur.archlinux.org/cgit/aur.git/snapshot/foo-base.tar.gz?h=foo-base&id=abc123");
+    }
+
+    #[test]
+    fn search_and_sort_keys_parse_from_their_names() {
+        for &by in SEARCH_BYS {
+            assert_eq!(by.to_string().parse::<SearchBy>().unwrap(), by);
+        }
+        for &sort in &[SortBy::Popularity, SortBy::Votes, SortBy::Name, SortBy::Modified] {
+            assert_eq!(sort.to_string().parse::<SortBy>().unwrap(), sort);
+        }
+        match "Name".parse::<SearchBy>() {
+            Err(Error::InvalidSearchBy(ref s)) if s == "Name" => (),
+            r => panic!("expected an invalid search field, got {:?}", r),
+        }
+        match "stars".parse::<SortBy>() {
+            Err(Error::InvalidSortBy(ref s)) if s == "stars" => (),
+            r => panic!("expected an invalid sort key, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn versions_maps_found_names() {
+        let server = TestServer::new(vec![ok(&envelope("multiinfo", &[
+            package(1, "foo", &[("Version", r#""1.2-1""#)]),
+            package(2, "bar", &[("Version", r#""2:0.1-3""#)]),
+        ]))]);
+        let versions = server.aur().versions(vec!["foo", "missing", "bar"]).unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions["foo"], "1.2-1");
+        assert_eq!(versions["bar"], "2:0.1-3");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn to_json_parses_back_to_the_same_package() {
+        let pkg = parse(&package(1, "foo", &[
+            ("OutOfDate", "1550000000"),
+            ("Maintainer", "null"),
+            ("Depends", r#"["bar>=2"]"#),
+            ("OptDepends", r#"["baz: extras"]"#),
+            ("License", r#"["MIT"]"#),
+            ("Keywords", r#"["tool"]"#),
+            ("CoMaintainers", r#"["bob"]"#),
+        ]));
+        let copy = Package::from_json(pkg.to_json()).unwrap();
+        assert_eq!(format!("{:?}", Package { fetched_at: pkg.fetched_at, ..copy }), format!("{:?}", pkg));
+        let json = pkg.to_json();
+        let obj = json.as_object().unwrap();
+        assert_eq!(obj["Name"].as_string(), Some("foo"));
+        assert_eq!(obj["OutOfDate"].as_u64(), Some(1550000000));
+        assert!(obj["Maintainer"].is_null());
+    }
+
+    #[test]
+    fn maintenance_pages_are_service_unavailable() {
+        let page = b"<!DOCTYPE html><html><body>Down for maintenance</body></html>";
+        let server = TestServer::new(vec![
+            response("200 OK", &[("Content-Type", "text/html; charset=UTF-8")], page),
+            response("200 OK", &[], b"\n  <html>Maintenance</html>"),
+            response("200 OK", &[("Content-Type", "text/html")], page),
+            ok(&envelope("search", &[package(1, "foo", &[])])),
+        ]);
+        let aur = server.aur();
+        for _ in 0..2 {
+            match aur.search("foo") {
+                Err(Error::ServiceUnavailable) => (),
+                r => panic!("expected ServiceUnavailable, got {:?}", r),
+            }
+        }
+        let retrying = server.builder().max_retries(1).build().unwrap();
+        assert_eq!(retrying.search("foo").unwrap()[0].name, "foo");
+    }
+
+    #[test]
+    fn accept_language_is_sent_only_when_set() {
+        let body = envelope("search", &[package(1, "foo", &[])]);
+        let server = TestServer::new(vec![ok(&body), ok(&body)]);
+        server.aur().search("foo").unwrap();
+        server.builder().accept_language("de-DE").build().unwrap().search("foo").unwrap();
+        let requests = server.requests();
+        assert!(!requests[0].to_lowercase().contains("accept-language"));
+        assert!(requests[1].contains("Accept-Language: de-DE\r\n"));
+    }
+
+    #[test]
+    fn download_with_progress_reports_bytes_read() {
+        let tarball = vec![0x1f; 100000];
+        let server = TestServer::new(vec![
+            response("200 OK", &[("Content-Type", "application/x-gzip")], &tarball),
+            response("404 Not Found", &[], b""),
+        ]);
+        let aur = server.aur();
+        let pkg = parse(&package(1, "foo", &[]));
+        let mut calls = Vec::new();
+        assert_eq!(aur.download_with_progress(&pkg, |read, total| calls.push((read, total))).unwrap(), tarball);
+        assert!(calls.len() > 1);
+        assert!(calls.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(calls.last(), Some(&(tarball.len(), Some(tarball.len()))));
+        match aur.download_with_progress(&pkg, |_, _| ()) {
+            Err(Error::NotFound) => (),
+            r => panic!("expected NotFound, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn summary_reads_name_version_and_flag() {
+        let server = TestServer::new(vec![
+            ok(&envelope("multiinfo", &[package(1, "foo", &[("Version", r#""2.0-1""#), ("OutOfDate", "1550000000")])])),
+            ok(&envelope("multiinfo", &[])),
+        ]);
+        let aur = server.aur();
+        let summary = aur.summary("foo").unwrap().unwrap();
+        assert_eq!((&summary.name[..], &summary.version[..]), ("foo", "2.0-1"));
+        assert_eq!(summary.out_of_date.map(|t| t.timestamp()), Some(1550000000));
+        assert!(aur.summary("missing").unwrap().is_none());
+        match aur.summary("") {
+            Err(Error::EmptyArgument) => (),
+            r => panic!("expected EmptyArgument, got {:?}", r),
+        }
+        assert_eq!(query_values(&server.targets()[0], "type"), ["info"]);
+    }
+
+    #[test]
+    fn envelopes_without_type_are_accepted() {
+        let server = TestServer::new(vec![
+            ok(&format!(r#"{{"version":5,"resultcount":1,"results":[{}]}}"#, package(1, "foo", &[]))),
+            ok(r#"{"version":5,"resultcount":0,"results":[],"error":"Incorrect by field specified."}"#),
+            ok(r#"{"version":5,"resultcount":0}"#),
+        ]);
+        let aur = server.aur();
+        assert_eq!(aur.search("foo").unwrap()[0].name, "foo");
+        match aur.search("foo") {
+            Err(Error::Aur(ref msg)) if msg == "Incorrect by field specified." => (),
+            r => panic!("expected an AUR error, got {:?}", r),
+        }
+        match aur.search("foo") {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("expected an invalid response, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn recently_updated_is_newest_first() {
+        let server = TestServer::new(vec![ok(&envelope("search", &[
+            package(1, "old", &[("LastModified", "1400000000")]),
+            package(2, "new", &[("LastModified", "1600000000")]),
+            package(3, "mid", &[("LastModified", "1500000000")]),
+        ]))]);
+        let names: Vec<String> = server.aur().recently_updated("foo", 2).unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["new", "mid"]);
+    }
+
+    #[test]
+    fn sparse_search_objects_parse() {
+        let pkg = parse(&package(1, "foo", &[
+            ("URL", ""),
+            ("Description", "null"),
+            ("Maintainer", ""),
+            ("OutOfDate", ""),
+            ("Popularity", ""),
+        ]));
+        assert!(pkg.homepage.is_none());
+        assert!(pkg.description.is_none());
+        assert!(pkg.maintainer.is_none());
+        assert!(pkg.out_of_date.is_none());
+        assert_eq!(pkg.popularity, 0.0);
+        assert!(parse(&package(1, "foo", &[("URL", r#""""#)])).homepage.is_none());
+        assert!(Package::from_json(Json::from_str(&package(1, "", &[("Name", "")])).unwrap()).is_err());
+    }
+
+    #[test]
+    fn search_full_fills_in_details_in_search_order() {
+        let server = TestServer::new(vec![
+            ok(&envelope("search", &[package(2, "b", &[]), package(1, "a", &[]), package(3, "gone", &[])])),
+            ok(&envelope("multiinfo", &[
+                package(1, "a", &[("Depends", r#"["x"]"#)]),
+                package(2, "b", &[("Keywords", r#"["y"]"#)]),
+            ])),
+        ]);
+        let pkgs = server.aur().search_full("foo").unwrap();
+        let names: Vec<&str> = pkgs.iter().map(|p| &p.name[..]).collect();
+        assert_eq!(names, ["b", "a"]);
+        assert_eq!(pkgs[0].keywords, ["y"]);
+        assert_eq!(pkgs[1].depends, ["x"]);
+        assert_eq!(query_values(&server.targets()[1], "arg[]"), ["b", "a", "gone"]);
+    }
+
+    #[test]
+    fn empty_arguments_fail_before_any_request() {
+        let server = TestServer::new(vec![]);
+        let aur = server.aur();
+        let results = vec![
+            aur.info("").map(|_| ()),
+            aur.search("").map(|_| ()),
+            aur.search_by("", SearchBy::Maintainer).map(|_| ()),
+            aur.msearch("").map(|_| ()),
+            aur.multiinfo(vec!["foo", ""]).map(|_| ()),
+        ];
+        for result in results {
+            match result {
+                Err(Error::EmptyArgument) => (),
+                r => panic!("expected EmptyArgument, got {:?}", r),
+            }
+        }
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn packages_involving_merges_co_maintained_packages() {
+        let server = TestServer::new(vec![
+            ok(&envelope("search", &[package(1, "mine", &[]), package(2, "shared", &[])])),
+            ok(&envelope("search", &[package(2, "shared", &[]), package(3, "helped", &[("CoMaintainers", r#"["alice"]"#)])])),
+        ]);
+        let names: Vec<String> = server.aur().packages_involving("alice").unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["mine", "shared", "helped"]);
+        let targets = server.targets();
+        assert_eq!(query_values(&targets[0], "by"), ["maintainer"]);
+        assert_eq!(query_values(&targets[1], "by"), ["comaintainers"]);
+    }
+
+    #[test]
+    fn ping_ignores_the_answer_but_not_the_status() {
+        let server = TestServer::new(vec![
+            ok(r#"["aa","ab"]"#),
+            ok("not even JSON"),
+            response("503 Service Unavailable", &[], b""),
+        ]);
+        let aur = server.aur();
+        aur.ping().unwrap();
+        aur.ping().unwrap();
+        assert!(aur.ping().is_err());
+        assert_eq!(query_values(&server.targets()[0], "type"), ["suggest"]);
+        let closed = Aur::with_client(hyper::Client::new(), Url::parse("http://127.0.0.1:1/rpc/").unwrap());
+        assert!(closed.ping().is_err());
+    }
+
+    #[test]
+    fn age_and_staleness_count_from_now() {
+        let day = 24 * 60 * 60;
+        let created = (now().timestamp() - 30 * day).to_string();
+        let flagged = (now().timestamp() - 2 * day).to_string();
+        let pkg = parse(&package(1, "foo", &[("FirstSubmitted", &created), ("OutOfDate", &flagged)]));
+        assert_eq!(pkg.age().as_secs() / day as u64, 30);
+        assert_eq!(pkg.staleness().map(|d| d.as_secs() / day as u64), Some(2));
+        assert!(parse(&package(1, "foo", &[])).staleness().is_none());
+    }
 }
@@ -0,0 +1,147 @@
+//! Canned responses for testing code that uses this crate, without a live AUR.
+//!
+//! Only available with the `testing` feature, which isn't meant for production builds.
+//!
+//! Each response is keyed by query parameters, as the crate sends them: searches use
+//! `type=search&arg=...`, info lookups `type=info&arg[]=...` (one `arg[]` per name), and
+//! suggestions `type=suggest&arg=...`. A request is answered by the first response whose
+//! parameters all appear in it, so a key can leave out the ones that don't matter, like `v` and
+//! `by`.
+//!
+//! ```
+//! let aur = aur::Aur::from_responses(vec![
+//!     ("type=suggest&arg=pac", r#"["pacaur", "pacman-git"]"#),
+//! ]);
+//! assert_eq!(aur.suggest("pac").unwrap(), vec!["pacaur", "pacman-git"]);
+//! ```
+//!
+//! Searches and info lookups are answered with the RPC's usual envelope around the package
+//! objects. Search results only carry the basic fields below; info results may add `Depends`,
+//! `License` and the rest.
+//!
+//! ```
+//! let search = r#"{"version": 5, "type": "search", "resultcount": 1, "results": [{
+//!     "ID": 1, "Name": "foo", "PackageBaseID": 1, "PackageBase": "foo", "Version": "1.0-1",
+//!     "Description": "A package", "URL": null, "NumVotes": 3, "Popularity": 0.1,
+//!     "OutOfDate": null, "Maintainer": "alice", "FirstSubmitted": 1500000000,
+//!     "LastModified": 1600000000, "URLPath": "/cgit/aur.git/snapshot/foo.tar.gz"
+//! }]}"#;
+//! let info = r#"{"version": 5, "type": "multiinfo", "resultcount": 1, "results": [{
+//!     "ID": 1, "Name": "foo", "PackageBaseID": 1, "PackageBase": "foo", "Version": "1.0-1",
+//!     "Description": "A package", "URL": null, "NumVotes": 3, "Popularity": 0.1,
+//!     "OutOfDate": null, "Maintainer": "alice", "FirstSubmitted": 1500000000,
+//!     "LastModified": 1600000000, "URLPath": "/cgit/aur.git/snapshot/foo.tar.gz",
+//!     "Depends": ["bar>=2"], "License": ["MIT"]
+//! }]}"#;
+//! let missing = r#"{"version": 5, "type": "multiinfo", "resultcount": 0, "results": []}"#;
+//! let aur = aur::Aur::from_responses(vec![
+//!     ("type=search&arg=foo", search),
+//!     ("type=info&arg[]=foo", info),
+//!     ("type=info", missing),
+//! ]);
+//!
+//! assert_eq!(aur.search("foo").unwrap()[0].votes, 3);
+//! assert_eq!(aur.info("foo").unwrap().unwrap().depends, vec!["bar>=2"]);
+//! assert!(aur.info("bar").unwrap().is_none());
+//! ```
+
+use std::io::{self, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper;
+use hyper::net::{NetworkConnector, NetworkStream};
+use url::form_urlencoded;
+
+type Responses = Arc<Vec<(Vec<(String, String)>, String)>>;
+
+/// Serves canned responses, picking the first whose query parameters all appear in the request.
+/// Requests that match nothing get a 404.
+pub struct MockConnector {
+    responses: Responses,
+}
+
+impl MockConnector {
+    pub fn new<'a, I>(responses: I) -> MockConnector
+        where I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        MockConnector {
+            responses: Arc::new(responses.into_iter()
+                                .map(|(query, body)| (form_urlencoded::parse(query.as_bytes()), body.to_owned()))
+                                .collect()),
+        }
+    }
+}
+
+impl NetworkConnector for MockConnector {
+    type Stream = MockStream;
+
+    fn connect(&self, _: &str, _: u16, _: &str) -> hyper::Result<MockStream> {
+        Ok(MockStream {
+            responses: self.responses.clone(),
+            request: Vec::new(),
+            response: None,
+        })
+    }
+}
+
+pub struct MockStream {
+    responses: Responses,
+    request: Vec<u8>,
+    response: Option<Cursor<Vec<u8>>>,
+}
+
+impl MockStream {
+    fn respond(&self) -> Vec<u8> {
+        let request = String::from_utf8_lossy(&self.request);
+        let target = request.split(' ').nth(1).unwrap_or("");
+        let query = match target.find('?') {
+            Some(i) => form_urlencoded::parse(target[i + 1..].as_bytes()),
+            None => Vec::new(),
+        };
+        let body = self.responses.iter()
+            .find(|&&(ref want, _)| want.iter().all(|pair| query.contains(pair)))
+            .map(|&(_, ref body)| body);
+        let (status, body) = match body {
+            Some(body) => ("200 OK", &body[..]),
+            None => ("404 Not Found", ""),
+        };
+        format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status, body.len(), body).into_bytes()
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.response.is_none() {
+            self.response = Some(Cursor::new(self.respond()));
+        }
+        self.response.as_mut().unwrap().read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.request.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for MockStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok("127.0.0.1:443".parse().unwrap())
+    }
+
+    fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+//! Sending requests through an HTTP proxy.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use hyper;
+use hyper::net::{HttpStream, NetworkConnector, NetworkStream, Ssl};
+
+/// The longest proxy response head we'll read before giving up.
+const MAX_HEAD_LEN: usize = 8192;
+
+/// Connects through an HTTP proxy. `https` URLs get a `CONNECT` tunnel to the real host and TLS
+/// through it; `http` requests are sent to the proxy itself, with absolute-form targets.
+pub struct ProxyConnector<S: Ssl> {
+    host: String,
+    port: u16,
+    ssl: S,
+}
+
+impl<S: Ssl> ProxyConnector<S> {
+    pub fn new(host: String, port: u16, ssl: S) -> ProxyConnector<S> {
+        ProxyConnector {
+            host: host,
+            port: port,
+            ssl: ssl,
+        }
+    }
+}
+
+impl<S: Ssl> NetworkConnector for ProxyConnector<S> {
+    type Stream = ProxyStream<S::Stream>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<Self::Stream> {
+        let mut stream = HttpStream(try!(TcpStream::connect(&(&self.host[..], self.port))));
+        if scheme == "https" {
+            try!(tunnel(&mut stream, host, port));
+            self.ssl.wrap_client(stream, host).map(ProxyStream::Tunnel)
+        } else {
+            Ok(ProxyStream::Forward(AbsoluteForm::new(stream, format!("http://{}:{}", host, port))))
+        }
+    }
+}
+
+/// A connection made by `ProxyConnector`.
+pub enum ProxyStream<S> {
+    /// Through a tunnel to the real host.
+    Tunnel(S),
+    /// To the proxy itself.
+    Forward(AbsoluteForm),
+}
+
+impl<S: NetworkStream> Read for ProxyStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            ProxyStream::Tunnel(ref mut s) => s.read(buf),
+            ProxyStream::Forward(ref mut s) => s.stream.read(buf),
+        }
+    }
+}
+
+impl<S: NetworkStream> Write for ProxyStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            ProxyStream::Tunnel(ref mut s) => s.write(buf),
+            ProxyStream::Forward(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            ProxyStream::Tunnel(ref mut s) => s.flush(),
+            ProxyStream::Forward(ref mut s) => s.stream.flush(),
+        }
+    }
+}
+
+impl<S: NetworkStream> NetworkStream for ProxyStream<S> {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        match *self {
+            ProxyStream::Tunnel(ref mut s) => s.peer_addr(),
+            ProxyStream::Forward(ref mut s) => s.stream.peer_addr(),
+        }
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        match *self {
+            ProxyStream::Tunnel(ref s) => s.set_read_timeout(dur),
+            ProxyStream::Forward(ref s) => s.stream.set_read_timeout(dur),
+        }
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        match *self {
+            ProxyStream::Tunnel(ref s) => s.set_write_timeout(dur),
+            ProxyStream::Forward(ref s) => s.stream.set_write_timeout(dur),
+        }
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        match *self {
+            ProxyStream::Tunnel(ref mut s) => s.close(how),
+            ProxyStream::Forward(ref mut s) => s.stream.close(how),
+        }
+    }
+}
+
+/// Rewrites each request's target into absolute form (`GET http://host:port/path`), as a proxy
+/// expects, by inserting the origin after the method.
+///
+/// Requests are assumed to have no body, which holds for the `GET`s this crate sends.
+pub struct AbsoluteForm {
+    stream: HttpStream,
+    origin: String,
+    /// Whether the next space ends a request's method.
+    in_method: bool,
+    /// How much of the `\r\n\r\n` ending the current request head has been written.
+    head_end: usize,
+}
+
+impl AbsoluteForm {
+    fn new(stream: HttpStream, origin: String) -> AbsoluteForm {
+        AbsoluteForm {
+            stream: stream,
+            origin: origin,
+            in_method: true,
+            head_end: 0,
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.in_method {
+            return match buf.iter().position(|&b| b == b' ') {
+                Some(i) => {
+                    try!(self.stream.write_all(&buf[..i + 1]));
+                    try!(self.stream.write_all(self.origin.as_bytes()));
+                    self.in_method = false;
+                    self.head_end = 0;
+                    Ok(i + 1)
+                },
+                None => self.stream.write_all(buf).map(|_| buf.len()),
+            };
+        }
+        for (i, &b) in buf.iter().enumerate() {
+            self.head_end = if b == b"\r\n\r\n"[self.head_end] {
+                self.head_end + 1
+            } else if b == b'\r' {
+                1
+            } else {
+                0
+            };
+            if self.head_end == 4 {
+                try!(self.stream.write_all(&buf[..i + 1]));
+                self.in_method = true;
+                return Ok(i + 1);
+            }
+        }
+        self.stream.write_all(buf).map(|_| buf.len())
+    }
+}
+
+fn tunnel(stream: &mut HttpStream, host: &str, port: u16) -> io::Result<()> {
+    try!(write!(stream, "CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\n\r\n", host, port));
+    try!(stream.flush());
+
+    // Read the response head a byte at a time so we don't consume anything past it.
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "proxy response too long"));
+        }
+        if try!(stream.read(&mut byte)) == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "proxy closed the connection"));
+        }
+        head.push(byte[0]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let status = head.lines().next().unwrap_or("");
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::Other,
+                                format!("proxy refused to connect to {}:{}: {}", host, port, status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util::*;
+
+    /// Passes tunnelled connections through unencrypted.
+    struct NoSsl;
+
+    impl Ssl for NoSsl {
+        type Stream = HttpStream;
+
+        fn wrap_client(&self, stream: HttpStream, _: &str) -> hyper::Result<HttpStream> {
+            Ok(stream)
+        }
+
+        fn wrap_server(&self, stream: HttpStream) -> hyper::Result<HttpStream> {
+            Ok(stream)
+        }
+    }
+
+    #[test]
+    fn http_requests_use_absolute_form() {
+        let envelope = envelope("search", &[]);
+        let server = TestServer::new(vec![ok(&envelope), ok(&envelope)]);
+        let aur = ::AurBuilder::new()
+            .base_url("http://aur.example/rpc/")
+            .proxy("127.0.0.1", server.port())
+            .build()
+            .unwrap();
+        aur.search("foo").unwrap();
+        aur.search("bar").unwrap();
+
+        let requests = server.requests();
+        for (request, arg) in requests.iter().zip(&["foo", "bar"]) {
+            let target = format!("GET http://aur.example:80/rpc/?v=5&type=search&by=name-desc&arg={} HTTP/1.1\r\n", arg);
+            assert!(request.starts_with(&target), "{:?}", request);
+            assert!(request.contains("Host: aur.example\r\n"));
+        }
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[test]
+    fn https_requests_are_tunnelled() {
+        let server = TestServer::new(vec![ok(""), ok("tunnelled")]);
+        let connector = ProxyConnector::new("127.0.0.1".to_owned(), server.port(), NoSsl);
+        let mut stream = connector.connect("aur.example", 443, "https").unwrap();
+        stream.write_all(b"GET /rpc/ HTTP/1.1\r\nHost: aur.example\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0; 64];
+        while !response.ends_with(b"\r\n\r\ntunnelled") {
+            let len = stream.read(&mut buf).unwrap();
+            assert!(len > 0, "{:?}", String::from_utf8_lossy(&response));
+            response.extend_from_slice(&buf[..len]);
+        }
+
+        let requests = server.requests();
+        assert!(requests[0].starts_with("CONNECT aur.example:443 HTTP/1.1\r\n"));
+        assert!(requests[1].starts_with("GET /rpc/ HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn refused_tunnels_fail() {
+        let server = TestServer::new(vec![response("407 Proxy Authentication Required", &[], b"")]);
+        let connector = ProxyConnector::new("127.0.0.1".to_owned(), server.port(), NoSsl);
+        assert!(connector.connect("aur.example", 443, "https").is_err());
+    }
+}
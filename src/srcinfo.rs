@@ -0,0 +1,130 @@
+//! Parsing `.SRCINFO` files.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use Error;
+
+/// The package base section of a `.SRCINFO` file, plus the names of the packages it builds.
+///
+/// Per-package overrides in the `pkgname` sections aren't parsed.
+#[derive(Clone, Debug)]
+pub struct SrcInfo {
+    pub pkgbase: String,
+    pub pkgnames: Vec<String>,
+    pub pkgver: String,
+    pub pkgrel: String,
+    pub epoch: Option<String>,
+    pub depends: Vec<String>,
+    pub make_depends: Vec<String>,
+    pub check_depends: Vec<String>,
+    pub opt_depends: Vec<String>,
+    /// Every field in the package base section, including architecture-specific ones like
+    /// `depends_x86_64`. Each field's values are kept in file order.
+    pub fields: BTreeMap<String, Vec<String>>,
+}
+
+impl SrcInfo {
+    /// The values of `key` in the package base section.
+    pub fn get(&self, key: &str) -> &[String] {
+        self.fields.get(key).map(|v| &v[..]).unwrap_or(&[])
+    }
+}
+
+impl FromStr for SrcInfo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SrcInfo, Error> {
+        let mut pkgbase = None;
+        let mut pkgnames = Vec::new();
+        let mut fields: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(Error::InvalidResponse),
+            };
+            match key {
+                "pkgbase" => pkgbase = Some(value.to_owned()),
+                "pkgname" => pkgnames.push(value.to_owned()),
+                // Fields after the first pkgname belong to a package section.
+                _ if pkgbase.is_none() || !pkgnames.is_empty() => (),
+                _ => fields.entry(key.to_owned()).or_insert_with(Vec::new).push(value.to_owned()),
+            }
+        }
+
+        let mut info = SrcInfo {
+            pkgbase: match pkgbase {
+                Some(base) => base,
+                None => return Err(Error::InvalidResponse),
+            },
+            pkgnames: pkgnames,
+            pkgver: String::new(),
+            pkgrel: String::new(),
+            epoch: None,
+            depends: Vec::new(),
+            make_depends: Vec::new(),
+            check_depends: Vec::new(),
+            opt_depends: Vec::new(),
+            fields: fields,
+        };
+        match (info.get("pkgver").first().cloned(), info.get("pkgrel").first().cloned()) {
+            (Some(pkgver), Some(pkgrel)) => {
+                info.pkgver = pkgver;
+                info.pkgrel = pkgrel;
+            },
+            _ => return Err(Error::InvalidResponse),
+        }
+        info.epoch = info.get("epoch").first().cloned();
+        info.depends = info.get("depends").to_vec();
+        info.make_depends = info.get("makedepends").to_vec();
+        info.check_depends = info.get("checkdepends").to_vec();
+        info.opt_depends = info.get("optdepends").to_vec();
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRCINFO: &'static str = "\
+# Generated by mksrcinfo
+pkgbase = foo
+\tpkgver = 1.2
+\tpkgrel = 3
+\tepoch = 1
+\tdepends = bar>=2
+\tdepends = baz
+\tmakedepends = cmake
+\tdepends_x86_64 = lib64
+
+pkgname = foo
+\tdepends = ignored
+
+pkgname = foo-docs
+";
+
+    #[test]
+    fn parses_the_package_base() {
+        let info: SrcInfo = SRCINFO.parse().unwrap();
+        assert_eq!(info.pkgbase, "foo");
+        assert_eq!(info.pkgnames, vec!["foo", "foo-docs"]);
+        assert_eq!((&info.pkgver[..], &info.pkgrel[..]), ("1.2", "3"));
+        assert_eq!(info.epoch, Some("1".to_owned()));
+        assert_eq!(info.depends, vec!["bar>=2", "baz"]);
+        assert_eq!(info.make_depends, vec!["cmake"]);
+        assert!(info.check_depends.is_empty());
+        assert_eq!(info.get("depends_x86_64"), &["lib64".to_owned()]);
+        assert!(info.get("missing").is_empty());
+    }
+
+    #[test]
+    fn rejects_incomplete_files() {
+        assert!("pkgver = 1\npkgrel = 1".parse::<SrcInfo>().is_err());
+        assert!("pkgbase = foo\npkgver = 1".parse::<SrcInfo>().is_err());
+        assert!("pkgbase = foo\nnot a field".parse::<SrcInfo>().is_err());
+    }
+}
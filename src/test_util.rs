@@ -0,0 +1,224 @@
+//! Fixtures for the tests, and a local HTTP server answering with canned replies for tests that
+//! need real connections, statuses or headers.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
+use rustc_serialize::json::Json;
+use url::form_urlencoded;
+
+use {Aur, AurBuilder, Package};
+
+/// A package object shaped like a v5 search result, with `fields` replacing or adding to the
+/// defaults. An empty value removes the field.
+pub fn package(id: u64, name: &str, fields: &[(&str, &str)]) -> String {
+    let mut all: Vec<(String, String)> = vec![
+        ("ID", id.to_string()),
+        ("Name", format!("{:?}", name)),
+        ("PackageBaseID", id.to_string()),
+        ("PackageBase", format!("{:?}", name)),
+        ("Version", r#""1.0-1""#.to_owned()),
+        ("Description", r#""A package""#.to_owned()),
+        ("URL", r#""https://example.com/""#.to_owned()),
+        ("NumVotes", "10".to_owned()),
+        ("Popularity", "0.5".to_owned()),
+        ("OutOfDate", "null".to_owned()),
+        ("Maintainer", r#""alice""#.to_owned()),
+        ("FirstSubmitted", "1500000000".to_owned()),
+        ("LastModified", "1600000000".to_owned()),
+        ("URLPath", format!("\"/cgit/aur.git/snapshot/{}.tar.gz\"", name)),
+    ].into_iter().map(|(k, v)| (k.to_owned(), v)).collect();
+    for &(key, value) in fields {
+        all.retain(|&(ref k, _)| k != key);
+        if !value.is_empty() {
+            all.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    let fields: Vec<String> = all.iter().map(|&(ref k, ref v)| format!("{:?}:{}", k, v)).collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+/// Parse a package object.
+pub fn parse(json: &str) -> Package {
+    Package::from_json(Json::from_str(json).unwrap()).unwrap()
+}
+
+/// A successful v5 response envelope around `results`.
+pub fn envelope(typ: &str, results: &[String]) -> String {
+    format!(r#"{{"version":5,"type":"{}","resultcount":{},"results":[{}]}}"#,
+            typ, results.len(), results.join(","))
+}
+
+/// Debug messages logged by any thread since `logs` was first called.
+static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct CaptureLogs;
+
+impl Log for CaptureLogs {
+    fn enabled(&self, _: &LogMetadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &LogRecord) {
+        LOGS.lock().unwrap().push(record.args().to_string());
+    }
+}
+
+/// Every message logged at debug level or above since the first call.
+pub fn logs() -> Vec<String> {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(|max| {
+            max.set(LogLevelFilter::Debug);
+            Box::new(CaptureLogs)
+        }).unwrap()
+    });
+    LOGS.lock().unwrap().clone()
+}
+
+/// How the server answers a request.
+pub enum Reply {
+    /// Send these bytes as the whole response.
+    Raw(Vec<u8>),
+    /// Wait, then answer.
+    Delay(Duration, Box<Reply>),
+    /// Close the connection without answering.
+    Hangup,
+}
+
+/// A 200 response with `body`.
+pub fn ok(body: &str) -> Reply {
+    response("200 OK", &[], body.as_bytes())
+}
+
+/// A response with the given status line, extra headers and body.
+pub fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Reply {
+    let mut head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n", status, body.len());
+    for &(name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(body);
+    Reply::Raw(bytes)
+}
+
+/// Answers requests on any number of connections with its replies, in the order the requests
+/// arrive. Requests after the last reply get a 404.
+pub struct TestServer {
+    port: u16,
+    requests: Arc<Mutex<Vec<String>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl TestServer {
+    pub fn new<I: IntoIterator<Item = Reply>>(replies: I) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let replies = Arc::new(Mutex::new(replies.into_iter().collect::<VecDeque<_>>()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (theirs, count) = (requests.clone(), connections.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                count.fetch_add(1, Ordering::SeqCst);
+                let (replies, requests) = (replies.clone(), theirs.clone());
+                thread::spawn(move || serve(stream, &replies, &requests));
+            }
+        });
+        TestServer {
+            port: port,
+            requests: requests,
+            connections: connections,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The URL of `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    /// A client using this server as its RPC endpoint.
+    pub fn aur(&self) -> Aur {
+        self.builder().build().unwrap()
+    }
+
+    pub fn builder(&self) -> AurBuilder {
+        AurBuilder::new().base_url(&self.url("/rpc/"))
+    }
+
+    /// The heads of the requests received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The request targets (e.g. `/rpc/?v=5&type=info`) received so far.
+    pub fn targets(&self) -> Vec<String> {
+        self.requests()
+            .iter()
+            .map(|head| head.split(' ').nth(1).unwrap_or("").to_owned())
+            .collect()
+    }
+
+    /// How many connections have been opened.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+/// The decoded query parameters of a request target.
+pub fn query(target: &str) -> Vec<(String, String)> {
+    form_urlencoded::parse(target.splitn(2, '?').nth(1).unwrap_or("").as_bytes())
+}
+
+/// The values of `key` in a request target's query.
+pub fn query_values(target: &str, key: &str) -> Vec<String> {
+    query(target).into_iter().filter(|&(ref k, _)| k == key).map(|(_, v)| v).collect()
+}
+
+fn serve(mut stream: TcpStream, replies: &Mutex<VecDeque<Reply>>, requests: &Mutex<Vec<String>>) {
+    while let Some(head) = read_head(&mut stream) {
+        requests.lock().unwrap().push(head);
+        let reply = replies.lock().unwrap().pop_front();
+        if !send(&mut stream, reply.unwrap_or_else(|| response("404 Not Found", &[], b""))) {
+            return;
+        }
+    }
+}
+
+fn send(stream: &mut TcpStream, reply: Reply) -> bool {
+    match reply {
+        Reply::Raw(bytes) => stream.write_all(&bytes).is_ok(),
+        Reply::Delay(delay, reply) => {
+            thread::sleep(delay);
+            send(stream, *reply)
+        },
+        Reply::Hangup => false,
+    }
+}
+
+fn read_head(stream: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => head.push(byte[0]),
+            _ => return None,
+        }
+    }
+    Some(String::from_utf8_lossy(&head).into_owned())
+}
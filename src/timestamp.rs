@@ -0,0 +1,44 @@
+//! Serde helpers encoding `NaiveDateTime`s as Unix timestamps, the way the RPC does.
+
+use chrono::naive::datetime::NaiveDateTime;
+use serde::{Deserialize, Deserializer, Serializer};
+use serde::de::Error;
+
+pub fn serialize<S>(t: &NaiveDateTime, s: S) -> Result<S::Ok, S::Error>
+    where S: Serializer,
+{
+    s.serialize_i64(t.timestamp())
+}
+
+pub fn deserialize<'de, D>(d: D) -> Result<NaiveDateTime, D::Error>
+    where D: Deserializer<'de>,
+{
+    let secs = try!(i64::deserialize(d));
+    NaiveDateTime::from_timestamp_opt(secs, 0).ok_or_else(|| D::Error::custom("timestamp out of range"))
+}
+
+pub mod option {
+    use chrono::naive::datetime::NaiveDateTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S>(t: &Option<NaiveDateTime>, s: S) -> Result<S::Ok, S::Error>
+        where S: Serializer,
+    {
+        match *t {
+            Some(ref t) => s.serialize_some(&t.timestamp()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<NaiveDateTime>, D::Error>
+        where D: Deserializer<'de>,
+    {
+        match try!(Option::<i64>::deserialize(d)) {
+            Some(secs) => NaiveDateTime::from_timestamp_opt(secs, 0)
+                .map(Some)
+                .ok_or_else(|| D::Error::custom("timestamp out of range")),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Package versions, compared the way pacman's `vercmp` does.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use Error;
+
+/// A package version of the form `[epoch:]pkgver[-pkgrel]`.
+///
+/// Versions are ordered using libalpm's version comparison algorithm, so (for example)
+/// `1.0a < 1.0 < 1.0.1` and any epoch beats any version without one.
+#[derive(Clone, Debug)]
+pub struct Version {
+    pub epoch: u64,
+    pub pkgver: String,
+    pub pkgrel: Option<String>,
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Version, Error> {
+        let invalid = || Error::InvalidVersion(s.to_owned());
+        if s.is_empty() || s.chars().any(char::is_whitespace) {
+            return Err(invalid());
+        }
+
+        // Like alpm, only treat the prefix as an epoch if it's entirely digits.
+        let digits = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let (epoch, rest) = if s[digits..].starts_with(':') {
+            let epoch = if digits == 0 {
+                0
+            } else {
+                try!(s[..digits].parse().map_err(|_| invalid()))
+            };
+            (epoch, &s[digits + 1..])
+        } else {
+            (0, s)
+        };
+
+        let (pkgver, pkgrel) = match rest.rfind('-') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        if pkgver.is_empty() || pkgrel.map(str::is_empty).unwrap_or(false) {
+            return Err(invalid());
+        }
+
+        Ok(Version {
+            epoch: epoch,
+            pkgver: pkgver.to_owned(),
+            pkgrel: pkgrel.map(str::to_owned),
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.epoch != 0 {
+            try!(write!(f, "{}:", self.epoch));
+        }
+        try!(f.write_str(&self.pkgver));
+        if let Some(ref rel) = self.pkgrel {
+            try!(write!(f, "-{}", rel));
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    /// Compare two versions. The pkgrels are only compared if both versions have one.
+    fn cmp(&self, other: &Version) -> Ordering {
+        self.epoch.cmp(&other.epoch)
+            .then_with(|| rpmvercmp(&self.pkgver, &other.pkgver))
+            .then_with(|| match (&self.pkgrel, &other.pkgrel) {
+                (&Some(ref a), &Some(ref b)) => rpmvercmp(a, b),
+                _ => Ordering::Equal,
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+fn is_digit(b: u8) -> bool {
+    b.is_ascii_digit()
+}
+
+fn is_alpha(b: u8) -> bool {
+    b.is_ascii_alphabetic()
+}
+
+fn is_alnum(b: u8) -> bool {
+    b.is_ascii_alphanumeric()
+}
+
+/// A port of libalpm's `rpmvercmp`, which compares a single version component.
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() && j < b.len() {
+        let (sep_i, sep_j) = (i, j);
+        while i < a.len() && !is_alnum(a[i]) {
+            i += 1;
+        }
+        while j < b.len() && !is_alnum(b[j]) {
+            j += 1;
+        }
+        if i == a.len() || j == b.len() {
+            break;
+        }
+
+        // A longer separator wins.
+        if i - sep_i != j - sep_j {
+            return (i - sep_i).cmp(&(j - sep_j));
+        }
+
+        // Grab the next all-numeric or all-alphabetic segment from each.
+        let (start_i, start_j) = (i, j);
+        let numeric = is_digit(a[i]);
+        let in_segment: fn(u8) -> bool = if numeric { is_digit } else { is_alpha };
+        while i < a.len() && in_segment(a[i]) {
+            i += 1;
+        }
+        while j < b.len() && in_segment(b[j]) {
+            j += 1;
+        }
+        let (mut seg_a, mut seg_b) = (&a[start_i..i], &b[start_j..j]);
+
+        // The segments are of different types; numeric segments are newer.
+        if seg_b.is_empty() {
+            return if numeric { Ordering::Greater } else { Ordering::Less };
+        }
+
+        if numeric {
+            while seg_a.first() == Some(&b'0') {
+                seg_a = &seg_a[1..];
+            }
+            while seg_b.first() == Some(&b'0') {
+                seg_b = &seg_b[1..];
+            }
+            // The longer number (sans leading zeros) is bigger.
+            if seg_a.len() != seg_b.len() {
+                return seg_a.len().cmp(&seg_b.len());
+            }
+        }
+
+        match seg_a.cmp(seg_b) {
+            Ordering::Equal => (),
+            ord => return ord,
+        }
+    }
+
+    let (rest_a, rest_b) = (&a[i..], &b[j..]);
+    if rest_a.is_empty() && rest_b.is_empty() {
+        return Ordering::Equal;
+    }
+    // A remaining alphabetic segment (like the `a` in `1.0a`) never beats the end of the version.
+    if (rest_a.is_empty() && !is_alpha(rest_b[0])) || (!rest_a.is_empty() && is_alpha(rest_a[0])) {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_epoch_and_pkgrel() {
+        let ver = v("2:1.4.0-3");
+        assert_eq!((ver.epoch, &ver.pkgver[..], ver.pkgrel.as_ref().map(|r| &r[..])), (2, "1.4.0", Some("3")));
+        assert_eq!(ver.to_string(), "2:1.4.0-3");
+        let bare = v("1.0");
+        assert_eq!((bare.epoch, bare.pkgrel), (0, None));
+        for bad in &["", "1.0 beta", "1.0-", "2:"] {
+            assert!(bad.parse::<Version>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn compares_like_vercmp() {
+        let ascending = ["1.0a", "1.0", "1.0.1", "1.1", "1.10", "2.0", "1:0.1"];
+        for pair in ascending.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        assert_eq!(v("1.01"), v("1.1"));
+        assert_eq!(v("1.0-1"), v("1.0"));
+        assert!(v("1.0.a") < v("1.0.1"));
+        assert!(v("1.0.1-1") < v("1.0.1-2"));
+    }
+}